@@ -0,0 +1,188 @@
+//! beam module
+//!
+//! Contains a beam search decoder, for finding the most likely sentences in the transitions
+//! rather than sampling them
+
+use std::collections::HashSet;
+
+use crate::context::Context;
+use crate::token::Token;
+use crate::transitions::Transitions;
+
+
+/// Beam search decoder settings
+#[derive(Debug, Clone, PartialEq)]
+pub struct BeamSearch {
+    /// Number of partial sentences kept at each step
+    pub width: usize,
+    /// Sentence scores are divided by `length ^ length_normalization`, so longer sentences aren't
+    /// always beaten by shorter ones. 0 disables normalisation
+    pub length_normalization: f64,
+    /// The probability of a token already in the sentence is divided by this. 1 disables the
+    /// penalty
+    pub repetition_penalty: f64,
+}
+
+impl Default for BeamSearch {
+    fn default() -> Self {
+        Self {
+            width: 5,
+            length_normalization: 0.7,
+            repetition_penalty: 1.0,
+        }
+    }
+}
+
+/// A sentence found by the decoder, with its score
+#[derive(Debug, Clone, PartialEq)]
+pub struct Hypothesis {
+    pub tokens: Vec<String>,
+    /// Length normalised log probability of the sentence
+    pub score: f64,
+}
+
+/// A partial sentence on the beam
+#[derive(Clone)]
+struct Beam {
+    context: Context,
+    tokens: Vec<String>,
+    log_prob: f64,
+}
+
+impl BeamSearch {
+    /// Find the top_n highest scoring distinct sentences of up to max_tokens, continuing from the
+    /// context and the tokens already in it
+    pub fn decode(
+        &self,
+        transitions: &Transitions,
+        context: &Context,
+        prompt: Vec<String>,
+        max_tokens: usize,
+        top_n: usize,
+    ) -> Vec<Hypothesis> {
+        let mut beams = vec![Beam { context: context.clone(), tokens: prompt, log_prob: 0.0 }];
+        let mut finished: Vec<Hypothesis> = Vec::new();
+
+        while !beams.is_empty() {
+            let mut candidates: Vec<Beam> = Vec::new();
+
+            for beam in beams {
+                let next_tokens = match transitions.next_tokens(&beam.context.state()) {
+                    Some(next_tokens) if beam.tokens.len() < max_tokens => next_tokens,
+                    // Dead ends and sentences at the length limit are as finished as they get
+                    _ => {
+                        finished.push(self.hypothesis(beam));
+                        continue;
+                    }
+                };
+                let total: f64 = next_tokens.values().sum();
+
+                for (token, count) in next_tokens {
+                    let mut log_prob = beam.log_prob + (count / total).ln();
+
+                    match token {
+                        Token::Token(value) => {
+                            if beam.tokens.contains(value) {
+                                log_prob -= self.repetition_penalty.ln();
+                            }
+                            let mut candidate = beam.clone();
+                            candidate.context.push(token);
+                            candidate.tokens.push(value.clone());
+                            candidate.log_prob = log_prob;
+                            candidates.push(candidate);
+                        },
+                        Token::Boundary => {
+                            finished.push(self.hypothesis(Beam { log_prob, ..beam.clone() }));
+                        },
+                    }
+                }
+            }
+
+            candidates.sort_by(|a, b| self.score(b).total_cmp(&self.score(a)));
+            candidates.truncate(self.width);
+            beams = candidates;
+        }
+
+        finished.sort_by(|a, b| b.score.total_cmp(&a.score));
+        let mut seen = HashSet::new();
+        finished.retain(|hypothesis| seen.insert(hypothesis.tokens.clone()));
+        finished.truncate(top_n);
+
+        finished
+    }
+
+    /// The length normalised score of a beam, counting the closing boundary as a token
+    fn score(&self, beam: &Beam) -> f64 {
+        let length = (beam.tokens.len() + 1) as f64;
+        beam.log_prob / length.powf(self.length_normalization)
+    }
+
+    fn hypothesis(&self, beam: Beam) -> Hypothesis {
+        let score = self.score(&beam);
+        Hypothesis { tokens: beam.tokens, score }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::train::train_with_tokens;
+    use crate::TokenizerConfigs;
+
+    fn sentence(tokens: &[&str]) -> Vec<Token> {
+        let mut sentence = vec![Token::Boundary];
+        sentence.extend(tokens.iter().map(|token| Token::from(*token)));
+        sentence.push(Token::Boundary);
+        sentence
+    }
+
+    fn trained() -> Transitions {
+        let mut transitions = Transitions::new();
+        for _ in 0..3 {
+            train_with_tokens(sentence(&["the", "whale"]), &mut transitions);
+        }
+        train_with_tokens(sentence(&["the", "sea"]), &mut transitions);
+        train_with_tokens(sentence(&["a", "sea"]), &mut transitions);
+        transitions
+    }
+
+    #[test]
+    fn test_beam_search_most_likely() {
+        let context = Context::new(1, &TokenizerConfigs::Words);
+        let hypotheses = BeamSearch::default().decode(&trained(), &context, vec![], 10, 2);
+
+        let sentences: Vec<Vec<String>> = hypotheses.into_iter().map(|h| h.tokens).collect();
+        assert_eq!(sentences, vec![vec!["the", "whale"], vec!["the", "sea"]]);
+    }
+
+    #[test]
+    fn test_beam_search_from_prompt() {
+        let mut context = Context::new(1, &TokenizerConfigs::Words);
+        context.push(&Token::from("a"));
+        let hypotheses = BeamSearch::default()
+            .decode(&trained(), &context, vec!["a".to_string()], 10, 5);
+
+        assert_eq!(hypotheses.len(), 1);
+        assert_eq!(hypotheses[0].tokens, vec!["a", "sea"]);
+        assert_eq!(hypotheses[0].score, 0.0, "The only path from the prompt is certain");
+    }
+
+    #[test]
+    fn test_beam_search_repetition_penalty() {
+        let mut transitions = Transitions::new();
+        for _ in 0..5 {
+            train_with_tokens(sentence(&["ho", "ho", "ho"]), &mut transitions);
+        }
+        let context = Context::new(1, &TokenizerConfigs::Words);
+
+        // Fully length normalised, the likely "ho" -> "ho" transition runs to the length limit
+        let beam = BeamSearch { length_normalization: 1.0, ..Default::default() };
+        let unpenalized = beam.decode(&transitions, &context, vec![], 6, 1);
+        assert_eq!(unpenalized[0].tokens, vec!["ho"; 6]);
+
+        let beam = BeamSearch { repetition_penalty: 100.0, ..beam };
+        let penalized = beam.decode(&transitions, &context, vec![], 6, 1);
+        assert_eq!(penalized[0].tokens, vec!["ho"]);
+    }
+}
@@ -0,0 +1,121 @@
+//! budget module
+//!
+//! Contains a memory budget for training on streams too large to count exactly
+
+use crate::transitions::Transitions;
+
+
+/// Keeps the memory used by transitions under a budget with lossy counting
+///
+/// Every check_interval lines, and at the end of each document, the memory used by the
+/// transitions is estimated. Over budget, the least frequent transitions are dropped, as many as
+/// should leave the transitions in three quarters of the budget going by their average size, and
+/// every transition counted no more than the last of those with them. A transition dropped and then seen again
+/// starts counting from scratch, and can be dropped again at a later check, so any count can be
+/// low by up to the counts dropped at every check added up.
+///
+/// Only the counts are measured, not provenance, which dropping transitions doesn't shrink.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MemoryBudget {
+    /// Memory the transitions can use, in bytes
+    pub bytes: usize,
+    /// Lines trained between memory checks, as estimating the memory used walks every transition
+    pub check_interval: usize,
+    lines: usize,
+    max_dropped: u32,
+}
+
+impl MemoryBudget {
+    /// Construct a budget of this many bytes, checked every 1000 lines
+    pub fn new(bytes: usize) -> MemoryBudget {
+        MemoryBudget {
+            bytes,
+            check_interval: 1000,
+            lines: 0,
+            max_dropped: 0,
+        }
+    }
+
+    /// Count a trained line, and drop the least frequent transitions if it is time for a check
+    /// and they are over budget
+    pub fn line_trained(&mut self, transitions: &mut Transitions) {
+        self.lines += 1;
+        if self.lines.is_multiple_of(self.check_interval) {
+            self.enforce(transitions);
+        }
+    }
+
+    /// Drop the least frequent transitions, if they are over budget
+    pub fn enforce(&mut self, transitions: &mut Transitions) {
+        let usage = transitions.memory_usage();
+        if usage <= self.bytes {
+            return;
+        }
+
+        let mut counts: Vec<f64> = transitions.last_tokens()
+            .flat_map(|last_token| transitions.next_tokens(last_token).into_iter().flat_map(|next| next.values()))
+            .copied()
+            .collect();
+        if counts.is_empty() {
+            return;
+        }
+        counts.sort_by(f64::total_cmp);
+
+        // Counts are whole numbers unless weighted, so round the one to drop up to the next
+        let target = self.bytes / 4 * 3;
+        let to_drop = ((usage - target) as f64 / usage as f64 * counts.len() as f64).ceil() as usize;
+        let threshold = counts[to_drop.clamp(1, counts.len()) - 1].ceil();
+        transitions.retain(|_, _, count| count > threshold);
+        transitions.retain_backward(|_, _, count| count > threshold);
+
+        self.max_dropped += threshold as u32;
+    }
+
+    /// The highest counts of transitions dropped at each check added up, which is how far below
+    /// its true count any count could be
+    pub fn max_error(&self) -> u32 {
+        self.max_dropped
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::token::Token;
+
+    #[test]
+    fn test_memory_budget() {
+        let mut transitions = Transitions::new();
+        let common = (Token::from("the"), Token::from("cat"));
+        for i in 0..100 {
+            transitions.count_transition(&common.0, &common.1);
+            transitions.count_transition(&Token::from(format!("rare{}", i)), &Token::Boundary);
+        }
+        let usage = transitions.memory_usage();
+
+        // Under budget, nothing is dropped
+        let mut budget = MemoryBudget::new(usage);
+        budget.enforce(&mut transitions);
+        assert_eq!(transitions.len(), 101);
+        assert_eq!(budget.max_error(), 0);
+
+        let mut budget = MemoryBudget { check_interval: 2, ..MemoryBudget::new(usage / 2) };
+        budget.line_trained(&mut transitions);
+        assert_eq!(transitions.len(), 101, "Shouldn't check before the interval is up");
+        budget.line_trained(&mut transitions);
+
+        assert!(transitions.memory_usage() <= usage / 2);
+        assert_eq!(transitions.len(), 1);
+        assert_eq!(transitions.next_tokens(&common.0).unwrap()[&common.1], 100.0);
+        assert_eq!(budget.max_error(), 1);
+
+        // A transition dropped at one check can be dropped again at the next, losing both counts
+        for i in 0..100 {
+            transitions.count_transition(&Token::from(format!("rare{}", i)), &Token::Boundary);
+        }
+        budget.enforce(&mut transitions);
+        assert_eq!(transitions.len(), 1);
+        assert_eq!(budget.max_error(), 2);
+    }
+}
@@ -0,0 +1,331 @@
+//! chain module
+//!
+//! Contains the generic markov chain, over any symbol type
+
+use std::collections::HashMap;
+use std::hash::{BuildHasher, BuildHasherDefault, DefaultHasher, Hash};
+use rand::{Rng, SeedableRng};
+use rand::distr::Distribution;
+use rand::distr::weighted::WeightedIndex;
+use rand::rngs::StdRng;
+
+use crate::error::Error;
+use crate::token::Token;
+use crate::train::train_with_tokens;
+use crate::transitions::Transitions;
+
+
+/// A first order markov chain over arbitrary symbols, like events in a log or notes in a melody
+///
+/// Each trained sequence is bounded by a Token::Boundary, and each generated sequence runs from
+/// one boundary to the next. A generated sequence also ends at a state whose counts can't be
+/// sampled from, without an error; `pick_next_token` returns it.
+///
+/// # Examples
+/// ```rust
+/// use rusty_markov::MarkovChain;
+///
+/// #[derive(Hash, PartialEq, Eq, Clone, Debug)]
+/// enum Click { Home, Search, Product, Checkout }
+///
+/// let mut chain = MarkovChain::new();
+/// chain.train([Click::Home, Click::Search, Click::Product, Click::Checkout]);
+///
+/// let session: Vec<Click> = chain.collect();
+/// assert_eq!(session, vec![Click::Home, Click::Search, Click::Product, Click::Checkout]);
+/// ```
+pub struct MarkovChain<T = String> {
+    transitions: Transitions<T>,
+    rng: StdRng,
+    temperature: f64,
+    last_token: Token<T>,
+}
+
+impl<T: Hash + Eq + Clone> Default for MarkovChain<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T: Hash + Eq + Clone> MarkovChain<T> {
+    pub fn new() -> Self {
+        Self {
+            transitions: Transitions::new(),
+            rng: StdRng::from_os_rng(),
+            temperature: 1.0,
+            last_token: Token::Boundary,
+        }
+    }
+
+    /// Construct a chain that also trains backward transitions, so it can generate sequences
+    /// leading up to a given symbol
+    pub fn with_backward() -> Self {
+        Self {
+            transitions: Transitions::with_backward(),
+            rng: StdRng::from_os_rng(),
+            temperature: 1.0,
+            last_token: Token::Boundary,
+        }
+    }
+
+    /// Train on one sequence of symbols, bounded on both ends
+    pub fn train<I: IntoIterator<Item = T>>(&mut self, sequence: I) {
+        let mut tokens = vec![Token::Boundary];
+        tokens.extend(sequence.into_iter().map(Token::Token));
+        tokens.push(Token::Boundary);
+
+        train_with_tokens(tokens, &mut self.transitions);
+    }
+
+    /// The trained transitions
+    pub fn transitions(&self) -> &Transitions<T> {
+        &self.transitions
+    }
+
+    /// The trained transitions, for training with other sources
+    pub fn transitions_mut(&mut self) -> &mut Transitions<T> {
+        &mut self.transitions
+    }
+
+    /// Take the trained transitions out of the chain
+    pub fn into_transitions(self) -> Transitions<T> {
+        self.transitions
+    }
+
+    /// Restart the random number generator from a seed, so what is generated from here on can
+    /// be repeated
+    pub fn seed(&mut self, seed: u64) {
+        self.rng = StdRng::seed_from_u64(seed);
+    }
+
+    /// Set the temperature next tokens are picked with. The temperature starts at 1, which picks
+    /// them in proportion to their counts. Lower temperatures favour the most common tokens more,
+    /// and higher ones flatten the odds out.
+    pub fn set_temperature(&mut self, temperature: f64) {
+        self.temperature = temperature;
+    }
+
+    /// The temperature next tokens are picked with
+    pub fn temperature(&self) -> f64 {
+        self.temperature
+    }
+
+    /// Pick a next token, following the given state
+    ///
+    /// Returns None if nothing is known to follow the state, and an error if what follows it
+    /// can't be sampled from
+    pub fn pick_next_token(&mut self, state: &Token<T>) -> Result<Option<&Token<T>>, Error> {
+        pick_next_token_with_temperature(&self.transitions, state, self.temperature, &mut self.rng)
+    }
+
+    /// Pick a previous token, leading up to the given token
+    pub fn pick_previous_token(&mut self, token: &Token<T>) -> Result<Option<&Token<T>>, Error> {
+        pick_previous_token(&self.transitions, token, &mut self.rng)
+    }
+
+    /// Walk backward from a token to the start of a sequence, returning the symbols leading up to
+    /// it in their forward order
+    ///
+    /// Gives up on a walk that, with the token itself, would be longer than max_tokens, or one
+    /// that reaches a token with no known previous tokens (or with counts that can't be sampled
+    /// from, whose error is dropped), returning None. Requires backward transitions.
+    pub fn walk_backward(&mut self, token: &Token<T>, max_tokens: usize) -> Option<Vec<T>> {
+        walk_backward(&self.transitions, token, max_tokens, &mut self.rng)
+    }
+}
+
+impl<T: Hash + Eq + Clone> Iterator for MarkovChain<T> {
+    type Item = T;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let picked = pick_next_token_with_temperature(
+            &self.transitions, &self.last_token, self.temperature, &mut self.rng
+        );
+        // Counts that can't be sampled from are a dead end, like a state with no counts at all
+        self.last_token = match picked {
+            Ok(Some(token)) => token.clone(),
+            _ => Token::Boundary
+        };
+
+        // Wrap up a new Token for moving out
+        match &self.last_token {
+            Token::Token(value) => Some(value.clone()),
+            _ => None,
+        }
+    }
+}
+
+/// Sample a next token from the transitions following the given state, weighted by counts
+///
+/// Returns None if the state is not in the transitions, and an error if its counts can't be
+/// sampled from
+pub fn pick_next_token<'a, T: Hash + Eq + Clone, R: Rng>(
+    transitions: &'a Transitions<T>, state: &Token<T>, rng: &mut R
+) -> Result<Option<&'a Token<T>>, Error> {
+    pick_next_token_with_temperature(transitions, state, 1.0, rng)
+}
+
+/// Sample a next token from the transitions following the given state, weighted by counts raised
+/// to the power of 1 / temperature
+///
+/// Returns None if the state is not in the transitions, and an error if its counts can't be
+/// sampled from
+pub fn pick_next_token_with_temperature<'a, T: Hash + Eq + Clone, R: Rng>(
+    transitions: &'a Transitions<T>, state: &Token<T>, temperature: f64, rng: &mut R
+) -> Result<Option<&'a Token<T>>, Error> {
+    match transitions.next_tokens(state) {
+        Some(next_tokens) => sample_token(next_tokens, temperature, rng).map(Some),
+        None => Ok(None),
+    }
+}
+
+/// Sample a previous token from the backward transitions leading up to the given token, weighted
+/// by counts
+pub fn pick_previous_token<'a, T: Hash + Eq + Clone, R: Rng>(
+    transitions: &'a Transitions<T>, token: &Token<T>, rng: &mut R
+) -> Result<Option<&'a Token<T>>, Error> {
+    match transitions.previous_tokens(token) {
+        Some(previous_tokens) => sample_token(previous_tokens, 1.0, rng).map(Some),
+        None => Ok(None),
+    }
+}
+
+/// Walk backward from a token to the start of a sequence, returning the symbols leading up to it
+/// in their forward order, or None if the walk gives up (see `MarkovChain::walk_backward`)
+pub(crate) fn walk_backward<T: Hash + Eq + Clone, R: Rng>(
+    transitions: &Transitions<T>, token: &Token<T>, max_tokens: usize, rng: &mut R
+) -> Option<Vec<T>> {
+    let mut preceding = Vec::new();
+    let mut current = token.clone();
+
+    loop {
+        // The token itself counts toward max_tokens
+        if preceding.len() >= max_tokens {
+            return None;
+        }
+        current = pick_previous_token(transitions, &current, rng).ok().flatten()?.clone();
+        match &current {
+            Token::Token(value) => preceding.push(value.clone()),
+            Token::Boundary => break,
+        }
+    }
+
+    preceding.reverse();
+    Some(preceding)
+}
+
+/// Sample a token from transition counts, weighted by counts raised to the power of
+/// 1 / temperature
+///
+/// Errors if the counts are empty, all zero, or otherwise can't be weighted by
+pub(crate) fn sample_token<'a, T: Hash, R: Rng>(
+    trans_map: &'a HashMap<Token<T>, f64>, temperature: f64, rng: &mut R
+) -> Result<&'a Token<T>, Error> {
+    let (mut counts, tokens) = decompose_transitions(trans_map);
+    if temperature != 1.0 {
+        // Scaling by the highest count first keeps the powers from overflowing
+        let max = counts.iter().copied().fold(0.0, f64::max);
+        for count in &mut counts {
+            *count = (*count / max).powf(1.0 / temperature);
+        }
+    }
+
+    let dist = WeightedIndex::new(counts)?;
+
+    Ok(tokens[dist.sample(rng)])
+}
+
+/// Decompose next_token transitions into a pair of arrays, ready for use in the rand lib
+///
+/// The order of a HashMap is random in every process, so the tokens are put in the order of a
+/// hash that isn't, for a seed to pick the same tokens again after a restart
+fn decompose_transitions<T: Hash>(trans_map: &HashMap<Token<T>, f64>) -> (Vec<f64>, Vec<&Token<T>>) {
+    let hasher = BuildHasherDefault::<DefaultHasher>::default();
+    let mut transitions: Vec<(u64, &Token<T>, f64)> = trans_map.iter()
+        .map(|(token, &count)| (hasher.hash_one(token), token, count))
+        .collect();
+    transitions.sort_unstable_by_key(|&(hash, _, _)| hash);
+
+    transitions.into_iter().map(|(_, token, count)| (count, token)).unzip()
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_chain_over_integers() {
+        let mut chain: MarkovChain<u32> = MarkovChain::new();
+        chain.train([1, 2, 3]);
+
+        assert_eq!(chain.by_ref().collect::<Vec<u32>>(), vec![1, 2, 3]);
+        // Generation starts over after reaching the boundary
+        assert_eq!(chain.collect::<Vec<u32>>(), vec![1, 2, 3]);
+    }
+
+    #[test]
+    fn test_chain_walk_backward() {
+        let mut chain: MarkovChain<char> = MarkovChain::with_backward();
+        chain.train("abc".chars());
+
+        assert_eq!(chain.walk_backward(&Token::Token('c'), 10), Some(vec!['a', 'b']));
+        assert_eq!(chain.walk_backward(&Token::Token('a'), 10), Some(vec![]));
+        assert_eq!(chain.walk_backward(&Token::Token('z'), 10), None);
+        assert_eq!(chain.walk_backward(&Token::Token('c'), 1), None, "Should give up on long walks");
+        assert_eq!(chain.walk_backward(&Token::Token('c'), 3), Some(vec!['a', 'b']));
+        assert_eq!(chain.walk_backward(&Token::Token('c'), 2), None, "Should count the token itself");
+    }
+
+    #[test]
+    fn test_chain_seed_and_temperature() {
+        let mut chain: MarkovChain<u32> = MarkovChain::new();
+        for i in 0..10 {
+            chain.train([i]);
+        }
+        chain.train([0]);
+
+        chain.seed(7);
+        let first: Vec<Option<u32>> = (0..20).map(|_| chain.next()).collect();
+        chain.seed(7);
+        let second: Vec<Option<u32>> = (0..20).map(|_| chain.next()).collect();
+        assert_eq!(first, second, "The same seed should generate the same symbols");
+
+        // Even from transitions trained separately, whose maps are in different random orders
+        let mut other: MarkovChain<u32> = MarkovChain::new();
+        for i in 0..10 {
+            other.train([i]);
+        }
+        other.train([0]);
+        other.seed(7);
+        let third: Vec<Option<u32>> = (0..20).map(|_| other.next()).collect();
+        assert_eq!(first, third, "The same seed should generate the same symbols in a new chain");
+
+        // Near zero, only the most common start is ever picked
+        chain.set_temperature(0.01);
+        for _ in 0..20 {
+            assert_eq!(chain.next(), Some(0));
+            assert_eq!(chain.next(), None);
+        }
+    }
+
+    #[test]
+    fn test_pick_next_token_errors() {
+        let mut transitions: Transitions<u32> = Transitions::new();
+        transitions.count_transition(&Token::Token(1), &Token::Token(2));
+        // A weight that isn't a number can't come from training, but can be set by hand
+        transitions.count_weighted_transition(&Token::Token(2), &Token::Token(3), f64::NAN);
+        let mut rng = StdRng::seed_from_u64(0);
+
+        assert_eq!(pick_next_token(&transitions, &Token::Token(1), &mut rng).unwrap(), Some(&Token::Token(2)));
+        assert_eq!(pick_next_token(&transitions, &Token::Token(9), &mut rng).unwrap(), None);
+        let error = pick_next_token(&transitions, &Token::Token(2), &mut rng).unwrap_err();
+        assert!(matches!(error, Error::Sampling(_)), "{}", error);
+    }
+
+    #[test]
+    fn test_chain_empty_training() {
+        let mut chain: MarkovChain<char> = MarkovChain::new();
+        assert!(chain.next().is_none(), "Should return None with no training data");
+    }
+}
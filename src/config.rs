@@ -0,0 +1,239 @@
+//! config module
+//!
+//! Contains config files of defaults for command line options
+
+use std::ffi::OsString;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use clap::{Arg, ArgAction, Command};
+use toml::{Table, Value};
+
+
+/// Defaults for command line options, read from a TOML file
+///
+/// Each key is the long name of an option, and sets its default. The options of a subcommand go
+/// in a table named after it, so a config like this sets the tokenizer and order for every
+/// subcommand, and the temperature for `generate`:
+/// ```toml
+/// tokenizer = "characters"
+/// order = 3
+///
+/// [generate]
+/// temperature = 0.8
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct Config {
+    table: Table,
+}
+
+impl Config {
+    /// Parse a config from TOML
+    pub fn from_toml(text: &str) -> Result<Config, String> {
+        text.parse::<Table>()
+            .map(|table| Config { table })
+            .map_err(|e| e.to_string())
+    }
+
+    /// Read a config from a TOML file
+    pub fn load(path: &Path) -> Result<Config, String> {
+        let text = fs::read_to_string(path).map_err(|e| e.to_string())?;
+        Config::from_toml(&text)
+    }
+
+    /// Set the defaults of a command's options to the values in the config, so options given on
+    /// the command line still override them
+    ///
+    /// Errors on keys that aren't options of the command, and on values the options don't take.
+    pub fn apply(&self, command: Command) -> Result<Command, String> {
+        apply_table(command, &self.table)
+    }
+}
+
+fn apply_table(mut command: Command, table: &Table) -> Result<Command, String> {
+    for (key, value) in table {
+        if let Value::Table(subtable) = value {
+            if command.find_subcommand(key).is_none() {
+                return Err(format!("there is no {} subcommand", key));
+            }
+            let mut result = Ok(());
+            command = command.mut_subcommand(key, |subcommand| {
+                match apply_table(subcommand.clone(), subtable) {
+                    Ok(subcommand) => subcommand,
+                    Err(e) => {
+                        result = Err(format!("[{}] {}", key, e));
+                        subcommand
+                    },
+                }
+            });
+            result?;
+            continue;
+        }
+
+        let values = match value {
+            Value::Array(values) => values.iter().map(option_value).collect::<Result<Vec<_>, _>>(),
+            value => option_value(value).map(|value| vec![value]),
+        }.map_err(|e| format!("{}: {}", key, e))?;
+
+        let arg = command.get_arguments()
+            .find(|arg| arg.get_long() == Some(key) && key != "config")
+            .ok_or_else(|| format!("there is no --{} option", key))?;
+        check_values(key, arg, &values)?;
+
+        let id = arg.get_id().clone();
+        command = command.mut_arg(id, |arg| arg.default_values(values));
+    }
+
+    Ok(command)
+}
+
+/// Check values parse the way they would for an option on the command line, since clap only
+/// checks defaults once they are used
+fn check_values(key: &str, arg: &Arg, values: &[String]) -> Result<(), String> {
+    // Flags don't take values on the command line, but their defaults are booleans
+    if !arg.get_action().takes_values() {
+        return match values.iter().all(|value| value == "true" || value == "false") {
+            true => Ok(()),
+            false => Err(format!("{} has to be true or false", key)),
+        };
+    }
+
+    let check = Command::new("config")
+        .no_binary_name(true)
+        .arg(Arg::new(key.to_string())
+            .long(key.to_string())
+            .value_parser(arg.get_value_parser().clone())
+            .action(ArgAction::Append));
+    let args = values.iter().map(|value| format!("--{}={}", key, value));
+    match check.try_get_matches_from(args) {
+        Ok(_) => Ok(()),
+        Err(e) => {
+            // Just the message, without the usage and help that follow it
+            let message = e.to_string();
+            let message = message.lines().next().unwrap_or_default();
+            Err(message.trim_start_matches("error: ").to_string())
+        },
+    }
+}
+
+/// The text of a TOML value, the way it would be written on the command line
+fn option_value(value: &Value) -> Result<String, String> {
+    match value {
+        Value::String(value) => Ok(value.clone()),
+        Value::Integer(value) => Ok(value.to_string()),
+        Value::Float(value) => Ok(value.to_string()),
+        Value::Boolean(value) => Ok(value.to_string()),
+        _ => Err("not a string, number or boolean".to_string()),
+    }
+}
+
+/// The --config file given in command line arguments, found before they are parsed so the
+/// config can set their defaults
+pub(crate) fn config_path(args: &[OsString]) -> Option<PathBuf> {
+    let mut args = args.iter().skip(1);
+    while let Some(arg) = args.next() {
+        let arg = arg.to_str()?;
+        if arg == "--" {
+            break;
+        }
+        if arg == "--config" {
+            return args.next().map(PathBuf::from);
+        }
+        if let Some(path) = arg.strip_prefix("--config=") {
+            return Some(PathBuf::from(path));
+        }
+    }
+
+    None
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Args, Commands, StripConfigs, TokenizerConfigs};
+    use clap::{CommandFactory, FromArgMatches};
+
+    fn parse(config: &str, args: &[&str]) -> Result<Args, String> {
+        let command = Config::from_toml(config)?.apply(Args::command())?;
+        let matches = command.try_get_matches_from(args).map_err(|e| e.to_string())?;
+        Args::from_arg_matches(&matches).map_err(|e| e.to_string())
+    }
+
+    #[test]
+    fn test_config_defaults_and_overrides() {
+        let config = "tokenizer = \"characters\"\norder = 3\nstrip = [\"gutenberg\", \"chapters\"]\n\n[generate]\ntemperature = 0.5\nkeywords = [\"a\", \"b\"]\n";
+
+        let args = parse(config, &["rusty-markov", "generate"]).unwrap();
+        assert_eq!(args.tokenizer, TokenizerConfigs::Characters);
+        assert_eq!(args.order, 3);
+        assert_eq!(args.strip, vec![StripConfigs::Gutenberg, StripConfigs::Chapters]);
+        let Commands::Generate(generate) = args.command else { panic!("Should generate") };
+        assert_eq!(generate.temperature, 0.5);
+        assert_eq!(generate.keywords, vec!["a", "b"]);
+
+        let args = parse(config, &["rusty-markov", "generate", "--order", "2", "--temperature", "2"]).unwrap();
+        assert_eq!(args.order, 2);
+        let Commands::Generate(generate) = args.command else { panic!("Should generate") };
+        assert_eq!(generate.temperature, 2.0);
+
+        let args = parse(config, &["rusty-markov", "stats", "--strip", "contents,page-numbers"]).unwrap();
+        assert_eq!(args.strip, vec![StripConfigs::Contents, StripConfigs::PageNumbers]);
+
+        // Global options apply to other subcommands too
+        let args = parse(config, &["rusty-markov", "stats"]).unwrap();
+        assert_eq!(args.order, 3);
+    }
+
+    #[test]
+    fn test_config_errors() {
+        let error = parse("order = 0", &["rusty-markov", "stats"]).unwrap_err();
+        assert!(error.starts_with("invalid value '0' for '--order <order>'"), "{}", error);
+        assert!(parse("tokenizer = \"bytes\"", &["rusty-markov", "stats"]).is_err());
+        assert_eq!(parse("colour = true", &["rusty-markov", "stats"]).unwrap_err(), "there is no --colour option");
+        assert_eq!(parse("[dance]\nx = 1", &["rusty-markov", "stats"]).unwrap_err(), "there is no dance subcommand");
+        assert_eq!(
+            parse("[stats]\ntemperature = 1", &["rusty-markov", "stats"]).unwrap_err(),
+            "[stats] there is no --temperature option",
+        );
+        assert!(Config::from_toml("order = ").is_err());
+
+        // Counts below MIN_COUNT are dropped, so weights that low would train nothing
+        let error = Args::try_parse_with_config_from(["rusty-markov", "stats", "--input", "in.txt:0.005"])
+            .unwrap_err()
+            .to_string();
+        assert!(error.contains("the weight of 'in.txt' has to be a number of at least 0.01"), "{}", error);
+        assert!(Args::try_parse_with_config_from(["rusty-markov", "stats", "--input", "in.txt:0.01"]).is_ok());
+
+        for option in ["--beam-width=0", "--repetition-penalty=0", "--repetition-penalty=-2"] {
+            assert!(Args::try_parse_with_config_from(["rusty-markov", "generate", option]).is_err(), "{}", option);
+        }
+        assert!(Args::try_parse_with_config_from(["rusty-markov", "generate", "--repetition-penalty=1"]).is_ok());
+    }
+
+    #[test]
+    fn test_generate_is_the_default_subcommand() {
+        let parse = |args: &[&str]| Args::try_parse_with_config_from(args.iter().copied());
+
+        assert!(matches!(parse(&["rusty-markov"]).unwrap().command, Commands::Generate(_)));
+        let args = parse(&["rusty-markov", "--order", "2"]).unwrap();
+        assert_eq!(args.order, 2);
+        assert!(matches!(args.command, Commands::Generate(_)));
+        let args = parse(&["rusty-markov", "--seed", "3", "-m", "5"]).unwrap();
+        let Commands::Generate(generate) = args.command else { panic!("Should generate") };
+        assert_eq!(generate.seed, Some(3));
+        assert!(matches!(parse(&["rusty-markov", "stats"]).unwrap().command, Commands::Stats(_)));
+
+        let error = parse(&["rusty-markov", "stats", "--seed", "3"]).unwrap_err().to_string();
+        assert!(error.starts_with("error: unexpected argument '--seed'"), "{}", error);
+        assert!(parse(&["rusty-markov", "--bogus"]).is_err());
+    }
+
+    #[test]
+    fn test_config_path() {
+        let args = |args: &[&str]| args.iter().map(OsString::from).collect::<Vec<_>>();
+        assert_eq!(config_path(&args(&["bin", "--config", "a.toml", "stats"])), Some(PathBuf::from("a.toml")));
+        assert_eq!(config_path(&args(&["bin", "stats", "--config=b.toml"])), Some(PathBuf::from("b.toml")));
+        assert_eq!(config_path(&args(&["bin", "stats"])), None);
+    }
+}
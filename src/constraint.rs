@@ -0,0 +1,113 @@
+//! constraint module
+//!
+//! Contains logic for checking generation constraints against trained transitions
+
+use std::collections::{HashSet, VecDeque};
+use std::fmt;
+
+use crate::context::Context;
+use crate::token::Token;
+use crate::transitions::Transitions;
+
+
+/// Reasons constrained generation can fail
+#[derive(Debug, Clone, PartialEq)]
+pub enum ConstraintError {
+    /// These required tokens can't be reached from the start of a sentence in the transitions
+    Unreachable(Vec<String>),
+    /// No sentence meeting the constraints was generated within this many attempts
+    AttemptsExhausted(usize),
+}
+
+impl fmt::Display for ConstraintError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConstraintError::Unreachable(tokens) => {
+                write!(f, "required tokens can never be generated: {}", tokens.join(", "))
+            },
+            ConstraintError::AttemptsExhausted(attempts) => {
+                write!(f, "no sentence meeting the constraints found in {} attempts", attempts)
+            },
+        }
+    }
+}
+
+impl std::error::Error for ConstraintError {}
+
+/// Walk the transitions breadth-first from the start of a sentence, collecting every token
+/// that could be generated
+///
+/// The context is used to work out the state following each token, so this works for chains of
+/// any order. It should be sitting at a boundary.
+pub fn reachable_tokens(transitions: &Transitions, context: &Context) -> HashSet<String> {
+    let mut reachable = HashSet::new();
+    let mut visited = HashSet::from([Token::Boundary]);
+    let mut queue = VecDeque::from([context.clone()]);
+
+    while let Some(context) = queue.pop_front() {
+        let next_tokens = match transitions.next_tokens(&context.state()) {
+            Some(next_tokens) => next_tokens,
+            None => continue,
+        };
+
+        for token in next_tokens.keys() {
+            if let Token::Token(value) = token {
+                reachable.insert(value.clone());
+            }
+
+            let mut next_context = context.clone();
+            next_context.push(token);
+            if visited.insert(next_context.state()) {
+                queue.push_back(next_context);
+            }
+        }
+    }
+
+    reachable
+}
+
+/// Check that every required token can be reached, returning the ones that can't as an error
+pub fn check_reachable(
+    transitions: &Transitions, context: &Context, required: &[&str]
+) -> Result<(), ConstraintError> {
+    let reachable = reachable_tokens(transitions, context);
+    let unreachable: Vec<String> = required.iter()
+        .filter(|token| !reachable.contains(**token))
+        .map(|token| token.to_string())
+        .collect();
+
+    match unreachable.is_empty() {
+        true => Ok(()),
+        false => Err(ConstraintError::Unreachable(unreachable)),
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::train::train_with_tokens;
+    use crate::TokenizerConfigs;
+
+    #[test]
+    fn test_reachable_tokens() {
+        let mut transitions = Transitions::new();
+        train_with_tokens(
+            vec![Token::Boundary, Token::from("a"), Token::from("b"), Token::Boundary],
+            &mut transitions,
+        );
+        // Only reachable from a state that is never reached from the start
+        train_with_tokens(vec![Token::from("x"), Token::from("y")], &mut transitions);
+
+        let context = Context::new(1, &TokenizerConfigs::Words);
+        assert_eq!(
+            reachable_tokens(&transitions, &context),
+            HashSet::from(["a".to_string(), "b".to_string()]),
+        );
+        assert_eq!(check_reachable(&transitions, &context, &["b", "a"]), Ok(()));
+        assert_eq!(
+            check_reachable(&transitions, &context, &["a", "y"]),
+            Err(ConstraintError::Unreachable(vec!["y".to_string()])),
+        );
+    }
+}
@@ -0,0 +1,125 @@
+//! context module
+//!
+//! Contains the rolling token history used as the state of a chain with an order above 1
+
+use std::collections::VecDeque;
+
+use crate::token::Token;
+use crate::TokenizerConfigs;
+
+
+/// The last `order` tokens seen since the most recent boundary, collapsed into a single state
+/// token for lookups in the transitions
+///
+/// With an order of 1, the state is just the last token, so an order 1 chain behaves exactly
+/// like one trained on bare token pairs. Right after a boundary the history is shorter than
+/// `order`, which keeps the states at the start of a sentence (or word) distinct from the same
+/// tokens appearing in the middle of one.
+#[derive(Debug, Clone)]
+pub struct Context {
+    order: usize,
+    separator: &'static str,
+    history: VecDeque<String>,
+}
+
+impl Context {
+    /// Construct a new Context, sitting at a boundary
+    pub fn new(order: usize, tokenizer_config: &TokenizerConfigs) -> Context {
+        let separator = match tokenizer_config {
+            TokenizerConfigs::Words => " ",
+            TokenizerConfigs::Characters | TokenizerConfigs::Graphemes => "",
+        };
+
+        Context {
+            order: order.max(1),
+            separator,
+            history: VecDeque::new(),
+        }
+    }
+
+    /// Shift a token into the history, a Token::Boundary clears it
+    pub fn push(&mut self, token: &Token) {
+        match token {
+            Token::Token(value) => {
+                if self.history.len() == self.order {
+                    self.history.pop_front();
+                }
+                self.history.push_back(value.clone());
+            },
+            Token::Boundary => self.history.clear(),
+        }
+    }
+
+    /// The most recent token, or a Token::Boundary at the start of a sentence (or word)
+    pub fn last_token(&self) -> Token {
+        match self.history.back() {
+            Some(value) => Token::from(value.as_str()),
+            None => Token::Boundary,
+        }
+    }
+
+    /// The state tokens for the current history, then for shorter and shorter parts of it, down to
+    /// just the last token, for backing off from a state that isn't known
+    pub fn backoff_states(&self) -> Vec<Token> {
+        if self.history.is_empty() {
+            return vec![Token::Boundary];
+        }
+
+        let parts: Vec<&str> = self.history.iter().map(String::as_str).collect();
+        (0..parts.len())
+            .map(|start| Token::from(parts[start..].join(self.separator)))
+            .collect()
+    }
+
+    /// Join generated tokens back into text, the way they were split up by the tokenizer
+    pub fn join(&self, tokens: &[String]) -> String {
+        tokens.join(self.separator)
+    }
+
+    /// The state token representing the current history
+    pub fn state(&self) -> Token {
+        if self.history.is_empty() {
+            return Token::Boundary;
+        }
+
+        let parts: Vec<&str> = self.history.iter().map(String::as_str).collect();
+        Token::from(parts.join(self.separator))
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_context_order_one_is_last_token() {
+        let mut context = Context::new(1, &TokenizerConfigs::Words);
+        assert_eq!(context.state(), Token::Boundary);
+
+        context.push(&Token::from("a"));
+        context.push(&Token::from("man"));
+        assert_eq!(context.state(), Token::from("man"));
+
+        context.push(&Token::Boundary);
+        assert_eq!(context.state(), Token::Boundary);
+    }
+
+    #[test]
+    fn test_context_characters_order_three() {
+        let mut context = Context::new(3, &TokenizerConfigs::Characters);
+        for c in ["a", "b"] {
+            context.push(&Token::from(c));
+        }
+        assert_eq!(context.state(), Token::from("ab"), "Should use a short history after a boundary");
+
+        for c in ["c", "d"] {
+            context.push(&Token::from(c));
+        }
+        assert_eq!(context.state(), Token::from("bcd"), "Should only keep the last 3 characters");
+        assert_eq!(
+            context.backoff_states(),
+            vec![Token::from("bcd"), Token::from("cd"), Token::from("d")],
+        );
+    }
+}
@@ -0,0 +1,365 @@
+//! decode module
+//!
+//! Contains the input layer training reads through, splitting raw bytes into lines and decoding
+//! them into text
+
+use std::borrow::Cow;
+use std::fmt;
+use std::io::{self, BufRead};
+
+use crate::error::Error;
+use crate::normalize::normalize_text;
+use crate::{EncodingConfigs, InvalidUtf8Configs, NormalizationConfigs};
+
+
+/// Windows-1252 characters for the bytes 0x80 to 0x9F, where it differs from Latin-1. The five
+/// bytes it leaves undefined decode to the Latin-1 control characters, as browsers do.
+const WINDOWS_1252: [char; 32] = [
+    '\u{20AC}', '\u{0081}', '\u{201A}', '\u{0192}', '\u{201E}', '\u{2026}', '\u{2020}', '\u{2021}',
+    '\u{02C6}', '\u{2030}', '\u{0160}', '\u{2039}', '\u{0152}', '\u{008D}', '\u{017D}', '\u{008F}',
+    '\u{0090}', '\u{2018}', '\u{2019}', '\u{201C}', '\u{201D}', '\u{2022}', '\u{2013}', '\u{2014}',
+    '\u{02DC}', '\u{2122}', '\u{0161}', '\u{203A}', '\u{0153}', '\u{009D}', '\u{017E}', '\u{0178}',
+];
+
+/// Counts of what decoding the input came across
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct DecodeStats {
+    /// Lines read
+    pub lines: usize,
+    /// Bytes read, not counting line endings
+    pub bytes: usize,
+    /// Lines that weren't valid UTF-8
+    pub invalid_lines: usize,
+    /// Invalid UTF-8 sequences replaced by U+FFFD
+    pub replacements: usize,
+    /// Lines left out for not being valid UTF-8
+    pub skipped_lines: usize,
+    /// Windows (CRLF) line endings, which are read the same as LF ones
+    pub crlf_endings: usize,
+    /// Old Mac (lone CR) line endings, which are read the same as LF ones
+    pub cr_endings: usize,
+}
+
+impl fmt::Display for DecodeStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Decoded {} lines ({} bytes)", self.lines, self.bytes)?;
+        if self.invalid_lines > 0 {
+            write!(
+                f, ", {} not valid UTF-8 ({} skipped, {} invalid sequences replaced)",
+                self.invalid_lines, self.skipped_lines, self.replacements,
+            )?;
+        }
+        if self.crlf_endings > 0 || self.cr_endings > 0 {
+            write!(f, ", normalised {} CRLF and {} CR line endings", self.crlf_endings, self.cr_endings)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Reads raw bytes as lines of text, in an encoding, keeping count of what it comes across
+///
+/// Lines can end with LF, CRLF or a lone CR. UTF-8 lines that aren't valid are handled the way
+/// the invalid UTF-8 policy says, and the legacy encodings decode any byte. Decoded lines are
+/// normalised to a Unicode normalisation form, if one is set.
+///
+/// # Examples
+/// ```rust
+/// use std::io::Cursor;
+/// use rusty_markov::{Decoder, EncodingConfigs, InvalidUtf8Configs};
+///
+/// let mut decoder = Decoder::new(EncodingConfigs::Windows1252, InvalidUtf8Configs::Abort);
+/// let lines: Vec<String> = decoder.decode_lines(Cursor::new(b"\x93Hi\x94\r\ncaf\xe9\rend"))
+///     .collect::<Result<_, _>>()
+///     .unwrap();
+///
+/// assert_eq!(lines, ["\u{201C}Hi\u{201D}", "café", "end"]);
+/// assert_eq!(decoder.stats().crlf_endings, 1);
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct Decoder {
+    encoding: EncodingConfigs,
+    invalid_utf8: InvalidUtf8Configs,
+    normalization: NormalizationConfigs,
+    stats: DecodeStats,
+}
+
+impl Decoder {
+    /// Construct a decoder for an encoding, handling invalid UTF-8 the way invalid_utf8 says to
+    /// (when the encoding is UTF-8)
+    pub fn new(encoding: EncodingConfigs, invalid_utf8: InvalidUtf8Configs) -> Self {
+        Self {
+            encoding,
+            invalid_utf8,
+            normalization: NormalizationConfigs::None,
+            stats: DecodeStats::default(),
+        }
+    }
+
+    /// Set the encoding lines are decoded from
+    pub fn set_encoding(&mut self, encoding: EncodingConfigs) {
+        self.encoding = encoding;
+    }
+
+    /// Set what is done with lines that aren't valid UTF-8
+    pub fn set_invalid_utf8(&mut self, invalid_utf8: InvalidUtf8Configs) {
+        self.invalid_utf8 = invalid_utf8;
+    }
+
+    /// Set the Unicode normalisation form decoded lines are normalised to
+    pub fn set_normalization(&mut self, normalization: NormalizationConfigs) {
+        self.normalization = normalization;
+    }
+
+    /// Counts of everything decoded so far
+    pub fn stats(&self) -> &DecodeStats {
+        &self.stats
+    }
+
+    /// Read lines of text from input, numbering them from 1 in errors
+    ///
+    /// Lines that can't be read are errors, and so are lines that aren't valid UTF-8 unless the
+    /// policy says to skip them (which decodes them as empty lines) or decode them lossily.
+    pub fn decode_lines<R: BufRead>(&mut self, input: R) -> impl Iterator<Item = Result<String, Error>> {
+        let mut lines = RawLines { input, done: false };
+        let mut number = 0;
+
+        std::iter::from_fn(move || {
+            number += 1;
+            match lines.next()? {
+                Ok((line, ending)) => {
+                    match ending {
+                        LineEnding::Crlf => self.stats.crlf_endings += 1,
+                        LineEnding::Cr => self.stats.cr_endings += 1,
+                        LineEnding::Lf | LineEnding::None => (),
+                    }
+                    Some(self.decode_line(&line, number))
+                },
+                Err(source) => Some(Err(Error::Io { line: number, source })),
+            }
+        })
+    }
+
+    /// Decode one line, without its line ending
+    pub fn decode_line(&mut self, line: &[u8], number: usize) -> Result<String, Error> {
+        self.stats.lines += 1;
+        self.stats.bytes += line.len();
+
+        let decoded = match self.encoding {
+            EncodingConfigs::Utf8 => self.decode_utf8(line, number)?,
+            EncodingConfigs::Latin1 => line.iter().map(|&byte| char::from(byte)).collect(),
+            EncodingConfigs::Windows1252 => line.iter().map(|&byte| windows_1252(byte)).collect(),
+        };
+
+        Ok(match normalize_text(&decoded, &self.normalization) {
+            Cow::Borrowed(_) => decoded,
+            Cow::Owned(normalized) => normalized,
+        })
+    }
+
+    fn decode_utf8(&mut self, line: &[u8], number: usize) -> Result<String, Error> {
+        if let Ok(line) = std::str::from_utf8(line) {
+            return Ok(line.to_string());
+        }
+
+        self.stats.invalid_lines += 1;
+        match self.invalid_utf8 {
+            InvalidUtf8Configs::Skip => {
+                self.stats.skipped_lines += 1;
+                Ok(String::new())
+            },
+            InvalidUtf8Configs::Lossy => {
+                // The same as String::from_utf8_lossy, counting the replacements as it goes
+                let mut decoded = String::with_capacity(line.len());
+                for chunk in line.utf8_chunks() {
+                    decoded.push_str(chunk.valid());
+                    if !chunk.invalid().is_empty() {
+                        decoded.push(char::REPLACEMENT_CHARACTER);
+                        self.stats.replacements += 1;
+                    }
+                }
+                Ok(decoded)
+            },
+            InvalidUtf8Configs::Abort => Err(Error::InvalidUtf8 { line: number }),
+        }
+    }
+}
+
+fn windows_1252(byte: u8) -> char {
+    match byte {
+        0x80..=0x9F => WINDOWS_1252[usize::from(byte - 0x80)],
+        byte => char::from(byte),
+    }
+}
+
+/// How a raw line ended
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum LineEnding {
+    Lf,
+    Crlf,
+    Cr,
+    /// The last line of the input, with no line ending
+    None,
+}
+
+/// Splits raw bytes into lines, on LF, CRLF or a lone CR
+struct RawLines<R> {
+    input: R,
+    done: bool,
+}
+
+impl<R: BufRead> RawLines<R> {
+    /// The buffered input, retrying reads that were interrupted
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        loop {
+            match self.input.fill_buf() {
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e),
+                // Filled, so this returns the same buffer without reading again. The borrow
+                // checker won't let the first one be returned from inside the loop.
+                Ok(_) => return self.input.fill_buf(),
+            }
+        }
+    }
+
+    fn read_line(&mut self) -> io::Result<Option<(Vec<u8>, LineEnding)>> {
+        let mut line = Vec::new();
+        loop {
+            let buffer = self.fill_buf()?;
+            if buffer.is_empty() {
+                self.done = true;
+                return Ok(match line.is_empty() {
+                    true => None,
+                    false => Some((line, LineEnding::None)),
+                });
+            }
+
+            let Some(end) = buffer.iter().position(|&byte| byte == b'\n' || byte == b'\r') else {
+                let length = buffer.len();
+                line.extend_from_slice(buffer);
+                self.input.consume(length);
+                continue;
+            };
+            line.extend_from_slice(&buffer[..end]);
+            let ending = buffer[end];
+            self.input.consume(end + 1);
+
+            if ending == b'\n' {
+                return Ok(Some((line, LineEnding::Lf)));
+            }
+            // The LF of a CRLF can be at the start of the next buffer
+            return Ok(match self.fill_buf()?.first() {
+                Some(b'\n') => {
+                    self.input.consume(1);
+                    Some((line, LineEnding::Crlf))
+                },
+                _ => Some((line, LineEnding::Cr)),
+            });
+        }
+    }
+}
+
+impl<R: BufRead> Iterator for RawLines<R> {
+    type Item = io::Result<(Vec<u8>, LineEnding)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+
+        match self.read_line() {
+            Ok(line) => line.map(Ok),
+            Err(e) => {
+                self.done = true;
+                Some(Err(e))
+            },
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::io::{BufReader, Cursor};
+    use super::*;
+
+    fn decode(decoder: &mut Decoder, input: &[u8]) -> Vec<Result<String, Error>> {
+        decoder.decode_lines(Cursor::new(input.to_vec())).collect()
+    }
+
+    #[test]
+    fn test_decode_line_endings() {
+        // A one byte buffer splits every CRLF across two reads
+        let input = BufReader::with_capacity(1, Cursor::new(b"a\r\nb\rc\n\r\nd\r".to_vec()));
+        let lines: Vec<(Vec<u8>, LineEnding)> = RawLines { input, done: false }.map(Result::unwrap).collect();
+
+        assert_eq!(lines, vec![
+            (b"a".to_vec(), LineEnding::Crlf),
+            (b"b".to_vec(), LineEnding::Cr),
+            (b"c".to_vec(), LineEnding::Lf),
+            (b"".to_vec(), LineEnding::Crlf),
+            (b"d".to_vec(), LineEnding::Cr),
+        ]);
+
+        let mut decoder = Decoder::default();
+        let lines: Vec<String> = decode(&mut decoder, b"one\ntwo\r\nthree").into_iter().map(Result::unwrap).collect();
+        assert_eq!(lines, ["one", "two", "three"]);
+        assert_eq!(decoder.stats().lines, 3);
+        assert_eq!(decoder.stats().crlf_endings, 1);
+        assert_eq!(decoder.stats().to_string(), "Decoded 3 lines (11 bytes), normalised 1 CRLF and 0 CR line endings");
+    }
+
+    #[test]
+    fn test_decode_invalid_utf8() {
+        let input = b"caf\xc3\xa9\ncaf\xe9 \xff\xfe\nok";
+
+        let mut lossy = Decoder::new(EncodingConfigs::Utf8, InvalidUtf8Configs::Lossy);
+        let lines: Vec<String> = decode(&mut lossy, input).into_iter().map(Result::unwrap).collect();
+        assert_eq!(lines, ["café", "caf\u{FFFD} \u{FFFD}\u{FFFD}", "ok"]);
+        assert_eq!(lossy.stats(), &DecodeStats {
+            lines: 3, bytes: 14, invalid_lines: 1, replacements: 3, ..DecodeStats::default()
+        });
+
+        let mut skip = Decoder::new(EncodingConfigs::Utf8, InvalidUtf8Configs::Skip);
+        let lines: Vec<String> = decode(&mut skip, input).into_iter().map(Result::unwrap).collect();
+        assert_eq!(lines, ["café", "", "ok"]);
+        assert_eq!(skip.stats().skipped_lines, 1);
+        assert_eq!(
+            skip.stats().to_string(),
+            "Decoded 3 lines (14 bytes), 1 not valid UTF-8 (1 skipped, 0 invalid sequences replaced)",
+        );
+
+        let mut abort = Decoder::new(EncodingConfigs::Utf8, InvalidUtf8Configs::Abort);
+        let error = decode(&mut abort, input).remove(1).unwrap_err();
+        assert_eq!(error.line(), Some(2));
+    }
+
+    #[test]
+    fn test_decode_line() {
+        let mut lossy = Decoder::new(EncodingConfigs::Utf8, InvalidUtf8Configs::Lossy);
+        assert_eq!(lossy.decode_line(b"tw\xffo", 2).unwrap(), "tw\u{FFFD}o");
+
+        let mut abort = Decoder::new(EncodingConfigs::Utf8, InvalidUtf8Configs::Abort);
+        assert_eq!(abort.decode_line(b"one", 1).unwrap(), "one");
+        let error = abort.decode_line(b"tw\xffo", 2).unwrap_err();
+        assert_eq!(error.to_string(), "line 2: invalid UTF-8");
+        assert_eq!(abort.stats().lines, 2);
+        assert_eq!(abort.stats().invalid_lines, 1);
+    }
+
+    #[test]
+    fn test_decode_legacy_encodings() {
+        let input = b"caf\xe9 \x80\x93\x81";
+
+        let mut latin1 = Decoder::new(EncodingConfigs::Latin1, InvalidUtf8Configs::Abort);
+        assert_eq!(decode(&mut latin1, input)[0].as_ref().unwrap(), "café \u{80}\u{93}\u{81}");
+
+        let mut windows = Decoder::new(EncodingConfigs::Windows1252, InvalidUtf8Configs::Abort);
+        assert_eq!(decode(&mut windows, input)[0].as_ref().unwrap(), "café €\u{201C}\u{81}");
+        assert_eq!(windows.stats().invalid_lines, 0);
+
+        // The "™" Windows-1252 has at 0x99 is folded to "TM" by NFKC
+        windows.set_normalization(NormalizationConfigs::Nfkc);
+        assert_eq!(decode(&mut windows, b"caf\xe9\x99")[0].as_ref().unwrap(), "caféTM");
+    }
+}
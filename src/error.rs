@@ -0,0 +1,61 @@
+//! error module
+//!
+//! Contains the errors training and sampling can fail with
+
+use std::fmt;
+use std::io;
+
+use rand::distr::weighted::Error as WeightError;
+
+
+/// Reasons training or sampling can fail
+#[derive(Debug)]
+pub enum Error {
+    /// Reading this line of the input failed
+    Io { line: usize, source: io::Error },
+    /// This line of the input isn't valid UTF-8, and invalid lines abort training
+    InvalidUtf8 { line: usize },
+    /// The counts following a state can't be sampled from, such as when they are all zero. Only
+    /// counts set up by hand can be like this, not trained or loaded ones.
+    ///
+    /// Only picking a single token (`pick_next_token` and the like) returns this. Generating
+    /// treats such counts as a dead end, the same as a state nothing is known to follow, and
+    /// drops the error.
+    Sampling(WeightError),
+}
+
+impl Error {
+    /// The line of the input the error is on, if it is about the input
+    pub fn line(&self) -> Option<usize> {
+        match self {
+            Error::Io { line, .. } | Error::InvalidUtf8 { line } => Some(*line),
+            Error::Sampling(_) => None,
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Io { line, source } => write!(f, "line {}: {}", line, source),
+            Error::InvalidUtf8 { line } => write!(f, "line {}: invalid UTF-8", line),
+            Error::Sampling(e) => write!(f, "counts can't be sampled from: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io { source, .. } => Some(source),
+            Error::Sampling(e) => Some(e),
+            Error::InvalidUtf8 { .. } => None,
+        }
+    }
+}
+
+impl From<WeightError> for Error {
+    fn from(e: WeightError) -> Self {
+        Error::Sampling(e)
+    }
+}
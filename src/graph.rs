@@ -0,0 +1,308 @@
+//! graph module
+//!
+//! Contains export of trained transitions as a graph, for visualising small models
+
+use std::collections::{BTreeMap, BTreeSet, HashSet, VecDeque};
+
+use crate::token::Token;
+use crate::transitions::Transitions;
+use crate::WeightConfigs;
+
+
+/// A node of the transition graph
+///
+/// A Token::Boundary is split into a start node that sentences leave from and an end node that
+/// they arrive at, so the graph reads left to right
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum Node {
+    Start,
+    State(String),
+    End,
+}
+
+impl Node {
+    fn label(&self) -> &str {
+        match self {
+            Node::Start => "START",
+            Node::State(state) => state,
+            Node::End => "END",
+        }
+    }
+}
+
+/// A transition between two nodes
+#[derive(Debug, Clone, PartialEq)]
+pub struct Edge {
+    pub from: Node,
+    pub to: Node,
+    /// Times the transition was counted
+    pub count: f64,
+    /// Probability of the transition being taken from its state
+    pub probability: f64,
+}
+
+/// Options for exporting a transition graph
+#[derive(Debug, Clone)]
+pub struct GraphExport {
+    /// Edges counted fewer times than this are left out
+    pub min_count: f64,
+    /// Only export the nodes within depth edges of the states reached by picking this token
+    pub around: Option<String>,
+    /// Number of edges, in either direction, from the around token to include
+    pub depth: usize,
+    /// What edges are weighted by
+    pub weights: WeightConfigs,
+}
+
+impl Default for GraphExport {
+    fn default() -> Self {
+        GraphExport {
+            min_count: 1.0,
+            around: None,
+            depth: 1,
+            weights: WeightConfigs::Count,
+        }
+    }
+}
+
+impl GraphExport {
+    /// Collect the edges of the transitions that pass the filters, sorted by the nodes
+    ///
+    /// next_state works out the state that follows a state and the token picked from it, which
+    /// is just the token for a chain of order 1
+    pub fn edges<F: Fn(&Token, &Token) -> Token>(
+        &self, transitions: &Transitions, next_state: F
+    ) -> Vec<Edge> {
+        let mut seeds: HashSet<Node> = HashSet::new();
+        let mut edges: Vec<Edge> = Vec::new();
+
+        for state in transitions.last_tokens() {
+            // A state always has successors, or it wouldn't be there
+            let next_tokens = transitions.next_tokens(state).unwrap();
+            let total: f64 = next_tokens.values().sum();
+            let from = match state {
+                Token::Token(value) => Node::State(value.clone()),
+                Token::Boundary => Node::Start,
+            };
+
+            for (token, count) in next_tokens {
+                let to = match (token, next_state(state, token)) {
+                    (Token::Boundary, _) | (_, Token::Boundary) => Node::End,
+                    (_, Token::Token(value)) => Node::State(value),
+                };
+                if let Token::Token(value) = token && self.around.as_ref() == Some(value) {
+                    seeds.insert(to.clone());
+                }
+                if *count >= self.min_count {
+                    edges.push(Edge {
+                        from: from.clone(),
+                        to,
+                        count: *count,
+                        probability: count / total,
+                    });
+                }
+            }
+        }
+
+        if self.around.is_some() {
+            let nearby = neighbourhood(&edges, seeds, self.depth);
+            edges.retain(|edge| nearby.contains(&edge.from) && nearby.contains(&edge.to));
+        }
+
+        edges.sort_by(|a, b| (&a.from, &a.to).cmp(&(&b.from, &b.to)));
+        edges
+    }
+
+    /// Render the edges as a Graphviz DOT digraph
+    pub fn to_dot(&self, edges: &[Edge]) -> String {
+        let ids = node_ids(edges);
+        let mut dot = String::from("digraph markov {\n    rankdir=LR;\n");
+
+        for (node, id) in &ids {
+            let shape = match node {
+                Node::Start | Node::End => ", shape=doublecircle",
+                Node::State(_) => "",
+            };
+            dot.push_str(&format!("    {} [label={}{}];\n", id, dot_string(node.label()), shape));
+        }
+        for edge in edges {
+            dot.push_str(&format!(
+                "    {} -> {} [label={}, weight={}];\n",
+                ids[&edge.from], ids[&edge.to], dot_string(&self.weight(edge)), self.dot_weight(edge),
+            ));
+        }
+
+        dot.push_str("}\n");
+        dot
+    }
+
+    /// Render the edges as a GraphML document
+    pub fn to_graphml(&self, edges: &[Edge]) -> String {
+        let ids = node_ids(edges);
+        let mut graphml = String::from(
+            "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+            <graphml xmlns=\"http://graphml.graphdrawing.org/xmlns\">\n  \
+            <key id=\"label\" for=\"node\" attr.name=\"label\" attr.type=\"string\"/>\n  \
+            <key id=\"boundary\" for=\"node\" attr.name=\"boundary\" attr.type=\"boolean\">\
+            <default>false</default></key>\n  \
+            <key id=\"weight\" for=\"edge\" attr.name=\"weight\" attr.type=\"double\"/>\n  \
+            <graph id=\"markov\" edgedefault=\"directed\">\n",
+        );
+
+        for (node, id) in &ids {
+            graphml.push_str(&format!(
+                "    <node id=\"{}\"><data key=\"label\">{}</data>", id, xml_escape(node.label())
+            ));
+            if let Node::Start | Node::End = node {
+                graphml.push_str("<data key=\"boundary\">true</data>");
+            }
+            graphml.push_str("</node>\n");
+        }
+        for edge in edges {
+            graphml.push_str(&format!(
+                "    <edge source=\"{}\" target=\"{}\"><data key=\"weight\">{}</data></edge>\n",
+                ids[&edge.from], ids[&edge.to], self.weight(edge),
+            ));
+        }
+
+        graphml.push_str("  </graph>\n</graphml>\n");
+        graphml
+    }
+
+    fn weight(&self, edge: &Edge) -> String {
+        match self.weights {
+            WeightConfigs::Count => edge.count.to_string(),
+            WeightConfigs::Probability => format!("{:.4}", edge.probability),
+        }
+    }
+
+    /// Graphviz only takes whole number weights, so probabilities are given as percentages
+    fn dot_weight(&self, edge: &Edge) -> u64 {
+        match self.weights {
+            WeightConfigs::Count => edge.count.round() as u64,
+            WeightConfigs::Probability => (edge.probability * 100.0).round() as u64,
+        }
+    }
+}
+
+/// The nodes within depth edges of the seeds, following edges in either direction
+fn neighbourhood(edges: &[Edge], seeds: HashSet<Node>, depth: usize) -> HashSet<Node> {
+    let mut nearby = seeds.clone();
+    let mut queue: VecDeque<(Node, usize)> = seeds.into_iter().map(|node| (node, 0)).collect();
+
+    while let Some((node, distance)) = queue.pop_front() {
+        if distance == depth {
+            continue;
+        }
+        for edge in edges {
+            let neighbour = if edge.from == node {
+                &edge.to
+            } else if edge.to == node {
+                &edge.from
+            } else {
+                continue;
+            };
+            if nearby.insert(neighbour.clone()) {
+                queue.push_back((neighbour.clone(), distance + 1));
+            }
+        }
+    }
+
+    nearby
+}
+
+/// Short ids for every node of the edges, in order
+fn node_ids(edges: &[Edge]) -> BTreeMap<Node, String> {
+    let nodes: BTreeSet<&Node> = edges.iter()
+        .flat_map(|edge| [&edge.from, &edge.to])
+        .collect();
+
+    nodes.into_iter()
+        .enumerate()
+        .map(|(i, node)| {
+            let id = match node {
+                Node::Start => "start".to_string(),
+                Node::End => "end".to_string(),
+                Node::State(_) => format!("n{}", i),
+            };
+            (node.clone(), id)
+        })
+        .collect()
+}
+
+/// Quote a DOT string
+fn dot_string(value: &str) -> String {
+    format!("\"{}\"", value.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+/// Escape text for XML content and attributes
+fn xml_escape(value: &str) -> String {
+    value.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::train::train_with_tokens;
+
+    fn transitions() -> Transitions {
+        let mut transitions = Transitions::new();
+        for tokens in [vec!["Call", "me"], vec!["Call", "me"], vec!["Call", "\"Ishmael\""]] {
+            let mut line = vec![Token::Boundary];
+            line.extend(tokens.into_iter().map(Token::from));
+            line.push(Token::Boundary);
+            train_with_tokens(line, &mut transitions);
+        }
+        transitions
+    }
+
+    #[test]
+    fn test_graph_edges() {
+        let transitions = transitions();
+        let export = GraphExport { min_count: 2.0, ..GraphExport::default() };
+        let edges = export.edges(&transitions, |_, token| token.clone());
+
+        let call = Node::State("Call".to_string());
+        let me = Node::State("me".to_string());
+        assert_eq!(edges, vec![
+            Edge { from: Node::Start, to: call.clone(), count: 3.0, probability: 1.0 },
+            Edge { from: call.clone(), to: me.clone(), count: 2.0, probability: 2.0 / 3.0 },
+            Edge { from: me, to: Node::End, count: 2.0, probability: 1.0 },
+        ]);
+
+        let export = GraphExport { around: Some("Call".to_string()), ..GraphExport::default() };
+        let edges = export.edges(&transitions, |_, token| token.clone());
+        let nodes: Vec<(&Node, &Node)> = edges.iter().map(|edge| (&edge.from, &edge.to)).collect();
+        assert_eq!(nodes, vec![
+            (&Node::Start, &call),
+            (&call, &Node::State("\"Ishmael\"".to_string())),
+            (&call, &Node::State("me".to_string())),
+        ]);
+    }
+
+    #[test]
+    fn test_graph_formats() {
+        let transitions = transitions();
+        let export = GraphExport { weights: WeightConfigs::Probability, ..GraphExport::default() };
+        let edges = export.edges(&transitions, |_, token| token.clone());
+
+        let dot = export.to_dot(&edges);
+        assert!(dot.starts_with("digraph markov {\n"));
+        assert!(dot.contains("    start [label=\"START\", shape=doublecircle];\n"));
+        assert!(dot.contains("[label=\"\\\"Ishmael\\\"\"];\n"));
+        assert!(dot.contains("    n2 -> n3 [label=\"0.6667\", weight=67];\n"));
+
+        let graphml = export.to_graphml(&edges);
+        assert!(graphml.contains("attr.type=\"double\""));
+        assert!(graphml.contains("<data key=\"label\">&quot;Ishmael&quot;</data>"));
+        assert!(graphml.contains(
+            "<node id=\"end\"><data key=\"label\">END</data><data key=\"boundary\">true</data></node>"
+        ));
+        assert!(graphml.contains("<edge source=\"start\" target=\"n2\"><data key=\"weight\">1.0000</data></edge>"));
+    }
+}
@@ -0,0 +1,47 @@
+//! json module
+//!
+//! Contains helpers for writing JSON output by hand
+
+use crate::token::Token;
+
+
+/// Quote and escape a string for JSON
+pub fn json_string(value: &str) -> String {
+    let mut json = String::with_capacity(value.len() + 2);
+    json.push('"');
+    for c in value.chars() {
+        match c {
+            '"' => json.push_str("\\\""),
+            '\\' => json.push_str("\\\\"),
+            '\n' => json.push_str("\\n"),
+            '\r' => json.push_str("\\r"),
+            '\t' => json.push_str("\\t"),
+            c if (c as u32) < 0x20 => json.push_str(&format!("\\u{:04x}", c as u32)),
+            c => json.push(c),
+        }
+    }
+    json.push('"');
+
+    json
+}
+
+/// Render a token as a JSON string, with a Token::Boundary as `null`
+pub fn token_json(token: &Token) -> String {
+    match token {
+        Token::Token(value) => json_string(value),
+        Token::Boundary => "null".to_string(),
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_json_string() {
+        assert_eq!(json_string("plain"), r#""plain""#);
+        assert_eq!(json_string("\"quoted\"\\\n"), r#""\"quoted\"\\\n""#);
+        assert_eq!(json_string("\u{1}"), r#""\u0001""#);
+    }
+}
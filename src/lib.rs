@@ -1,12 +1,74 @@
+mod beam;
+mod budget;
+mod chain;
+mod constraint;
+mod config;
+mod context;
+mod decode;
+mod error;
 mod generator;
+mod graph;
+mod json;
+mod markup;
+mod mixture;
+mod model;
+mod normalize;
+mod originality;
+mod preprocess;
+mod provenance;
+mod prune;
+mod repl;
+#[cfg(feature = "serde")]
+mod saved;
+mod score;
+#[cfg(feature = "serde")]
+mod serve;
+mod stats;
 mod token;
+mod trace;
 mod tokenize;
 mod train;
 mod transitions;
 
 
-use clap::{command, Parser};
+use std::ffi::OsString;
+use std::path::PathBuf;
+
+use clap::{CommandFactory, FromArgMatches, Parser, Subcommand};
+use clap::builder::RangedU64ValueParser;
+use clap::error::ErrorKind;
+use config::config_path;
+pub use beam::{BeamSearch, Hypothesis};
+pub use budget::MemoryBudget;
+pub use chain::{pick_next_token, pick_next_token_with_temperature, pick_previous_token, MarkovChain};
+pub use config::Config;
+pub use constraint::ConstraintError;
+pub use context::Context;
+pub use decode::{DecodeStats, Decoder};
+pub use error::Error;
 pub use generator::MarkovGenerator;
+pub use graph::{Edge, GraphExport, Node};
+pub use markup::Extractor;
+pub use mixture::MixtureGenerator;
+pub use model::{GeneratorHandle, MarkovModel};
+pub use normalize::{normalize_text, Normalizer};
+pub use preprocess::Preprocessor;
+pub use provenance::Location;
+pub use prune::{Pruning, UNKNOWN};
+pub use repl::Repl;
+#[cfg(feature = "serde")]
+pub use saved::ModelSettings;
+pub use score::SentenceScore;
+#[cfg(feature = "serde")]
+pub use serve::Server;
+pub use stats::ModelStats;
+pub use token::Token;
+pub use trace::TraceStep;
+pub use train::{
+    tokenize_lines, tokenize_stream, train_with_context, train_with_stream, train_with_tokenizer,
+    train_with_tokens, untrain_with_context,
+};
+pub use transitions::{Transitions, MIN_COUNT};
 
 
 #[derive(Debug, Clone, PartialEq, clap::ValueEnum)]
@@ -17,15 +79,503 @@ pub enum BoundaryConfigs {
     SentenceEndings,
 }
 
+#[derive(Debug, Clone, PartialEq, clap::ValueEnum)]
+pub enum TokenizerConfigs {
+    /// Whitespace-separated words and punctuation are tokens
+    Words,
+    /// Characters are tokens, with each word bounded by boundaries (good for names and codes)
+    Characters,
+    /// Unicode grapheme clusters are tokens, with each word bounded by boundaries
+    Graphemes,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, clap::ValueEnum)]
+pub enum NormalizationConfigs {
+    /// Leave text as it is
+    #[default]
+    None,
+    /// Canonical composition, so accented letters are the same however they were typed
+    Nfc,
+    /// Compatibility composition, which also folds ligatures, full-width forms and the like
+    Nfkc,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, clap::ValueEnum)]
+pub enum EncodingConfigs {
+    /// UTF-8, with lines that aren't valid handled the way --invalid-utf8 says
+    #[default]
+    Utf8,
+    /// ISO-8859-1, where every byte is the character with the same number
+    Latin1,
+    /// Windows-1252, Latin-1 with printable characters like curly quotes in 0x80 to 0x9F
+    Windows1252,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, clap::ValueEnum)]
+pub enum InvalidUtf8Configs {
+    /// Leave lines that aren't valid UTF-8 out of training
+    #[default]
+    Skip,
+    /// Train on lines that aren't valid UTF-8 with the invalid bytes replaced by U+FFFD
+    Lossy,
+    /// Stop training with an error at the first line that isn't valid UTF-8
+    Abort,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, clap::ValueEnum)]
+pub enum InputFormats {
+    /// Plain text, read as it is
+    #[default]
+    Text,
+    /// HTML, read for its visible text, without scripts and styles
+    Html,
+    /// Markdown, read without code blocks, link URLs and formatting markers
+    Markdown,
+}
+
+#[derive(Debug, Clone, PartialEq, clap::ValueEnum)]
+pub enum StripConfigs {
+    /// The Project Gutenberg header up to the START marker, and the license from the END marker
+    Gutenberg,
+    /// Tables of contents, from a "Contents" heading to the text after its entries
+    Contents,
+    /// Chapter headings, like "CHAPTER 12. Biographical." or a roman numeral on its own line
+    Chapters,
+    /// Lines that are only a page number, and page markers like "[Pg 12]"
+    PageNumbers,
+}
+
+#[derive(Debug, Clone, PartialEq, clap::ValueEnum)]
+pub enum DecodeConfigs {
+    /// Sample each token at random, weighted by its transition counts
+    Sample,
+    /// Search for the most likely sentences
+    Beam,
+}
+
+#[derive(Debug, Clone, PartialEq, clap::ValueEnum)]
+pub enum TraceConfigs {
+    /// One JSON object per line for each generation step
+    Json,
+}
+
+#[derive(Debug, Clone, PartialEq, clap::ValueEnum)]
+pub enum OutputFormats {
+    /// Human readable text
+    Text,
+    /// A JSON object
+    Json,
+}
+
+#[derive(Debug, Clone, PartialEq, clap::ValueEnum)]
+pub enum GraphFormats {
+    /// Graphviz DOT
+    Dot,
+    /// GraphML XML
+    Graphml,
+}
+
+#[derive(Debug, Clone, PartialEq, clap::ValueEnum)]
+pub enum WeightConfigs {
+    /// Times each transition was counted
+    Count,
+    /// Probability of each transition being taken from its state
+    Probability,
+}
+
+#[derive(Debug, Clone, PartialEq, clap::ValueEnum)]
+pub enum ModelFormats {
+    Json,
+    Yaml,
+}
+
 /// A Markov chain text generator
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None)]
 pub struct Args {
-    /// Number of tokens to generate
-    #[arg(short, long, default_value_t = 100)]
+    #[command(subcommand)]
+    pub command: Commands,
+
+    /// TOML file of defaults for any of the options, with the options of a subcommand in a
+    /// table named after it (like [generate]). Options given on the command line override it.
+    #[arg(long, global = true)]
+    pub config: Option<PathBuf>,
+
+    /// Files to train on, instead of stdin, each with an optional weight to count its
+    /// transitions with (like ours.txt:5)
+    #[arg(short, long, global = true, value_parser = parse_source)]
+    pub input: Vec<Source>,
+
+    /// Load a model exported with --save (JSON, or YAML with a .yaml or .yml extension), and
+    /// only continue training it if there are --input files. It has to be given the same order,
+    /// tokenizer, boundaries, normalization and case folding it was trained with. Options that
+    /// need the training text (--ends-with, --around, --max-copy and --explain) can't be used.
+    #[cfg(feature = "serde")]
+    #[arg(long, global = true)]
+    pub load: Option<PathBuf>,
+
+    /// Scale the counts of the loaded model by this factor (between 0 and 1) before continuing
+    /// training, so older training fades out
+    #[cfg(feature = "serde")]
+    #[arg(long, global = true, requires = "load", value_parser = parse_factor)]
+    pub decay: Option<f64>,
+
+    /// Files to take back out of the model, after loading and training, each with the weight it
+    /// was trained with (like ours.txt:5)
+    #[arg(long, global = true, value_parser = parse_source)]
+    pub untrain: Vec<Source>,
+
+    /// Save the trained model (JSON, or YAML with a .yaml or .yml extension)
+    #[cfg(feature = "serde")]
+    #[arg(long, global = true)]
+    pub save: Option<PathBuf>,
+
+    /// Keep the memory used by training under this many bytes (with an optional K, M or G suffix)
+    /// by dropping the least frequent transitions, so counts become approximate
+    #[arg(long, global = true, value_parser = parse_bytes)]
+    pub memory_budget: Option<usize>,
+
+    /// Number of tokens to generate (words, for the character and grapheme tokenizers)
+    #[arg(short, long, default_value_t = 100, global = true)]
     pub max_tokens: usize,
 
     /// Boundary configuration for training
-    #[arg(short, long, value_enum, default_value = "line-endings")]
+    #[arg(short, long, value_enum, default_value = "line-endings", global = true)]
     pub boundaries: BoundaryConfigs,
+
+    /// Tokenizer used to split up the training text
+    #[arg(short, long, value_enum, default_value = "words", global = true)]
+    pub tokenizer: TokenizerConfigs,
+
+    /// Encoding of the input files (or stdin)
+    #[arg(long, value_enum, default_value = "utf8", global = true)]
+    pub encoding: EncodingConfigs,
+
+    /// What to do with input lines that aren't valid UTF-8
+    #[arg(long, value_enum, default_value = "skip", global = true)]
+    pub invalid_utf8: InvalidUtf8Configs,
+
+    /// Unicode normalisation form the training text is normalised to
+    #[arg(long, value_enum, default_value = "none", global = true)]
+    pub normalization: NormalizationConfigs,
+
+    /// Train on lowercased tokens, so "The" and "the" count as one, and restore their most
+    /// common casing when generating
+    #[arg(long, global = true)]
+    pub fold_case: bool,
+
+    /// Format of the input files (or stdin). Headings, paragraphs and list items of HTML and
+    /// Markdown are boundaries.
+    #[arg(long, value_enum, default_value = "text", global = true)]
+    pub input_format: InputFormats,
+
+    /// Boilerplate to strip out of each input before tokenizing it (comma separated, like
+    /// gutenberg,contents,chapters)
+    #[arg(long, value_enum, value_delimiter = ',', global = true)]
+    pub strip: Vec<StripConfigs>,
+
+    /// Number of preceding tokens considered when picking the next token
+    #[arg(short, long, default_value_t = 1, value_parser = RangedU64ValueParser::<usize>::new().range(1..), global = true)]
+    pub order: usize,
+}
+
+impl Args {
+    /// Parse the command line, taking defaults from the --config file if there is one
+    pub fn parse_with_config() -> Args {
+        Args::try_parse_with_config_from(std::env::args_os()).unwrap_or_else(|e| e.exit())
+    }
+
+    /// Parse command line arguments, taking defaults from the --config file if there is one
+    pub fn try_parse_with_config_from<I, T>(args: I) -> Result<Args, clap::Error>
+    where
+        I: IntoIterator<Item = T>,
+        T: Into<OsString> + Clone,
+    {
+        let args: Vec<OsString> = args.into_iter().map(Into::into).collect();
+        let mut command = Args::command();
+        if let Some(path) = config_path(&args) {
+            let config = Config::load(&path)
+                .map_err(|e| command.error(ErrorKind::Io, format!("could not load {}: {}", path.display(), e)))?;
+            command = config.apply(command)
+                .map_err(|e| Args::command().error(ErrorKind::InvalidValue, format!("in {}: {}", path.display(), e)))?;
+        }
+
+        // Without a subcommand, generate, taking options only generate has too. If that doesn't
+        // parse either, the error without it says better what is wrong.
+        let command = command.subcommand_required(false).arg_required_else_help(false);
+        let mut generate_args = args.clone();
+        generate_args.insert(generate_args.len().min(1), OsString::from("generate"));
+        let matches = match command.clone().try_get_matches_from(&args) {
+            Ok(matches) if matches.subcommand().is_some() => matches,
+            Ok(_) => command.try_get_matches_from(generate_args)?,
+            Err(e) if e.kind() == ErrorKind::UnknownArgument => {
+                command.try_get_matches_from(generate_args).map_err(|_| e)?
+            },
+            Err(e) => return Err(e),
+        };
+        Args::from_arg_matches(&matches)
+    }
+}
+
+#[derive(clap::Args, Debug)]
+pub struct GenerateArgs {
+    /// Generate from a mixture of models saved with --save instead of training, each with an
+    /// optional mixing weight (like poems.json:3). Give it more than once to mix several models,
+    /// all trained with the same tokenizer and order.
+    #[cfg(feature = "serde")]
+    #[arg(long = "model", value_parser = parse_source)]
+    pub models: Vec<Source>,
+
+    /// Generate a sentence ending with this token
+    #[arg(long, conflicts_with = "around")]
+    pub ends_with: Option<String>,
+
+    /// Generate a sentence growing outward in both directions from this token
+    #[arg(long, conflicts_with = "keywords")]
+    pub around: Option<String>,
+
+    /// Generate a sentence containing all of these tokens (comma separated)
+    #[arg(short, long, value_delimiter = ',', conflicts_with = "ends_with")]
+    pub keywords: Vec<String>,
+
+    /// Reject generated sentences that copy more than this many consecutive tokens from the
+    /// training text, and report the originality of each sentence
+    #[arg(long)]
+    pub max_copy: Option<usize>,
+
+    /// Number of sentences to try when generating with keywords or an originality guard
+    #[arg(long, default_value_t = 1000)]
+    pub max_attempts: usize,
+
+    /// How to pick generated tokens. A beam search can't be combined with --ends-with, --around,
+    /// --keywords, --max-copy or --trace.
+    #[arg(short, long, value_enum, default_value = "sample")]
+    pub decode: DecodeConfigs,
+
+    /// Temperature tokens are sampled with: below 1 favours the most common tokens, and above 1
+    /// flattens the odds out
+    #[arg(long, default_value_t = 1.0, value_parser = parse_temperature)]
+    pub temperature: f64,
+
+    /// Seed for sampling, to generate the same text again from the same model (or training
+    /// input) and options
+    #[arg(long)]
+    pub seed: Option<u64>,
+
+    /// Number of partial sentences kept at each step of a beam search
+    #[arg(long, default_value_t = 5, value_parser = RangedU64ValueParser::<usize>::new().range(1..))]
+    pub beam_width: usize,
+
+    /// Exponent of the sentence length that beam search scores are divided by (0 to disable)
+    #[arg(long, default_value_t = 0.7)]
+    pub length_normalization: f64,
+
+    /// Divides the probability of repeating a token during a beam search (1 to disable)
+    #[arg(long, default_value_t = 1.0, value_parser = parse_penalty)]
+    pub repetition_penalty: f64,
+
+    /// Annotate each generated token with the training file lines it could have come from
+    #[arg(long)]
+    pub explain: bool,
+
+    /// When the current state is unknown, back off to shorter parts of the history instead of
+    /// ending the sentence
+    #[arg(long)]
+    pub backoff: bool,
+
+    /// Output a trace of each generation step, instead of the generated text
+    #[arg(long, value_enum, conflicts_with_all = ["ends_with", "around", "keywords", "max_copy", "explain"])]
+    pub trace: Option<TraceConfigs>,
+
+    /// Number of distinct sentences to output from a beam search
+    #[arg(long, default_value_t = 1)]
+    pub top_n: usize,
+}
+
+/// Parse a temperature, which has to be positive
+fn parse_temperature(value: &str) -> Result<f64, String> {
+    match value.parse::<f64>() {
+        Ok(temperature) if temperature > 0.0 && temperature.is_finite() => Ok(temperature),
+        _ => Err(format!("'{}' is not a positive number", value)),
+    }
+}
+
+/// Parse a penalty, which has to be at least 1 to make what it penalises less likely
+fn parse_penalty(value: &str) -> Result<f64, String> {
+    match value.parse::<f64>() {
+        Ok(penalty) if penalty >= 1.0 && penalty.is_finite() => Ok(penalty),
+        _ => Err(format!("'{}' is not a number of at least 1", value)),
+    }
+}
+
+/// Parse a factor between 0 and 1
+#[cfg(feature = "serde")]
+fn parse_factor(value: &str) -> Result<f64, String> {
+    match value.parse::<f64>() {
+        Ok(factor) if (0.0..=1.0).contains(&factor) => Ok(factor),
+        _ => Err(format!("'{}' is not a number between 0 and 1", value)),
+    }
+}
+
+/// A file, and the weight its transitions are counted (or mixed) with
+#[derive(Debug, Clone, PartialEq)]
+pub struct Source {
+    pub path: PathBuf,
+    pub weight: f64,
+}
+
+/// Parse a file path, with an optional :weight suffix
+///
+/// Weights below MIN_COUNT are rejected, as counts that low are dropped, so a document trained
+/// with one would add nothing at all.
+fn parse_source(value: &str) -> Result<Source, String> {
+    if let Some((path, weight)) = value.rsplit_once(':')
+        && let Ok(weight) = weight.parse::<f64>()
+    {
+        return match weight >= MIN_COUNT && weight.is_finite() {
+            true => Ok(Source { path: PathBuf::from(path), weight }),
+            false => Err(format!("the weight of '{}' has to be a number of at least {}", path, MIN_COUNT)),
+        };
+    }
+
+    Ok(Source { path: PathBuf::from(value), weight: 1.0 })
+}
+
+/// Parse a number of bytes, with an optional K, M or G (binary) suffix
+fn parse_bytes(value: &str) -> Result<usize, String> {
+    let (number, multiplier) = match value.to_ascii_uppercase().chars().last() {
+        Some('K') => (&value[..value.len() - 1], 1 << 10),
+        Some('M') => (&value[..value.len() - 1], 1 << 20),
+        Some('G') => (&value[..value.len() - 1], 1 << 30),
+        _ => (value, 1),
+    };
+    number.parse::<usize>()
+        .ok()
+        .and_then(|number| number.checked_mul(multiplier))
+        .ok_or_else(|| format!("'{}' is not a number of bytes, like 512M", value))
+}
+
+#[derive(Subcommand, Debug)]
+pub enum Commands {
+    /// Train a model, and output it (or save it with --save)
+    #[cfg(feature = "serde")]
+    Train(TrainArgs),
+    /// Generate sentences (the default, when no subcommand is given)
+    Generate(GenerateArgs),
+    /// Score how likely sentences are under the model
+    Score(ScoreArgs),
+    /// Report statistics of the trained model
+    Stats(StatsArgs),
+    /// Merge models saved with --save into one, and output it
+    #[cfg(feature = "serde")]
+    Merge(MergeArgs),
+    /// Export the transition graph of the trained model
+    Export(ExportArgs),
+    /// Prune rare transitions and vocabulary from the model (usually one given with --load), and
+    /// output the pruned model
+    #[cfg(feature = "serde")]
+    Prune(PruneArgs),
+    /// Explore the model interactively, reading commands from stdin (so training has to be from
+    /// --input files, or a model given with --load)
+    Repl,
+    /// Serve generation, scoring and statistics of the model as HTTP JSON endpoints
+    #[cfg(feature = "serde")]
+    Serve(ServeArgs),
+}
+
+#[cfg(feature = "serde")]
+#[derive(clap::Args, Debug)]
+pub struct TrainArgs {
+    /// Model format, when not saving with --save
+    #[arg(short, long, value_enum, default_value = "json")]
+    pub format: ModelFormats,
+}
+
+#[derive(clap::Args, Debug)]
+pub struct ScoreArgs {
+    /// Sentences to score
+    #[arg(required = true)]
+    pub sentences: Vec<String>,
+
+    /// Output format
+    #[arg(short, long, value_enum, default_value = "text")]
+    pub format: OutputFormats,
+}
+
+#[derive(clap::Args, Debug)]
+pub struct StatsArgs {
+    /// Output format
+    #[arg(short, long, value_enum, default_value = "text")]
+    pub format: OutputFormats,
+
+    /// Number of entries in each of the top lists
+    #[arg(long, default_value_t = 10)]
+    pub top: usize,
+}
+
+#[derive(clap::Args, Debug)]
+pub struct ExportArgs {
+    /// Graph format
+    #[arg(short, long, value_enum, default_value = "dot")]
+    pub format: GraphFormats,
+
+    /// What edges are weighted by
+    #[arg(short, long, value_enum, default_value = "count")]
+    pub weights: WeightConfigs,
+
+    /// Leave out transitions counted fewer times than this
+    #[arg(long, default_value_t = 1.0)]
+    pub min_count: f64,
+
+    /// Only export the neighbourhood of this token
+    #[arg(long)]
+    pub around: Option<String>,
+
+    /// Number of transitions, in either direction, from the around token to export
+    #[arg(long, default_value_t = 1, requires = "around")]
+    pub depth: usize,
+}
+
+#[cfg(feature = "serde")]
+#[derive(clap::Args, Debug)]
+pub struct MergeArgs {
+    /// Models to merge, each with an optional weight to scale its counts by (like ours.json:2)
+    #[arg(required = true, value_parser = parse_source)]
+    pub models: Vec<Source>,
+
+    /// Model format
+    #[arg(short, long, value_enum, default_value = "json")]
+    pub format: ModelFormats,
+}
+
+#[cfg(feature = "serde")]
+#[derive(clap::Args, Debug)]
+pub struct PruneArgs {
+    /// Model format
+    #[arg(short, long, value_enum, default_value = "json")]
+    pub format: ModelFormats,
+
+    /// Drop transitions counted fewer times than this
+    #[arg(long, default_value_t = 1.0)]
+    pub min_count: f64,
+
+    /// Drop states observed fewer times than this
+    #[arg(long, default_value_t = 1.0)]
+    pub min_state_count: f64,
+
+    /// Keep only this many of the most common tokens following each state
+    #[arg(long)]
+    pub keep_top: Option<usize>,
+
+    /// Replace tokens seen fewer times than this with <unk>
+    #[arg(long, default_value_t = 1.0)]
+    pub min_token_count: f64,
+}
+
+#[cfg(feature = "serde")]
+#[derive(clap::Args, Debug)]
+pub struct ServeArgs {
+    /// Address to listen on
+    #[arg(long, default_value = "127.0.0.1:8080")]
+    pub address: String,
 }
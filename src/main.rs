@@ -1,19 +1,481 @@
-use std::io;
+use std::fs::File;
+use std::io::{self, BufReader};
+#[cfg(feature = "serde")]
+use std::{fs, net::TcpListener, path::Path};
+use std::process;
+use std::sync::Arc;
 
-use clap::Parser;
-use rusty_markov::{Args, MarkovGenerator, BoundaryConfigs};
+use rusty_markov::{
+    Args, BeamSearch, Commands, DecodeConfigs, EncodingConfigs, GenerateArgs, GraphExport, GraphFormats, Location,
+    MarkovGenerator, MemoryBudget, OutputFormats, Repl, TraceConfigs
+};
+#[cfg(feature = "serde")]
+use rusty_markov::{
+    MergeArgs, MixtureGenerator, ModelFormats, ModelSettings, Normalizer, Pruning, Server, Transitions
+};
 
 fn main() {
-    let args = Args::parse();
-    read_stdin_lines(args.max_tokens, args.boundaries);
+    let args = Args::parse_with_config();
+    run(args);
 }
 
-/// Reads lines from stdin
-pub fn read_stdin_lines(count: usize, boundary_config: BoundaryConfigs) {
-    let stdin = io::stdin().lock();
+/// Runs the subcommand, training on the input files (or stdin) first for the ones that need a
+/// trained model
+pub fn run(args: Args) {
+    match &args.command {
+        #[cfg(feature = "serde")]
+        Commands::Train(train_args) => {
+            let mark = trained(&args);
+            if args.save.is_none() {
+                print_model(&settings(&args), mark.transitions(), mark.normalizer(), &train_args.format);
+            }
+        },
+        #[cfg(feature = "serde")]
+        Commands::Generate(generate_args) if !generate_args.models.is_empty() => {
+            generate_mixture(&args, generate_args);
+        },
+        Commands::Generate(generate_args) => {
+            check_decode(generate_args);
+            let mut mark = trained(&args);
+            generate_sentences(&mut mark, &args, generate_args);
+        },
+        Commands::Score(score_args) => {
+            let mark = trained(&args);
+            for sentence in &score_args.sentences {
+                let score = mark.score(sentence);
+                match score_args.format {
+                    OutputFormats::Text => println!("{}\t{}", score, sentence),
+                    OutputFormats::Json => println!("{}", score.to_json()),
+                }
+            }
+        },
+        Commands::Stats(stats_args) => {
+            let stats = trained(&args).stats(stats_args.top);
+            match stats_args.format {
+                OutputFormats::Text => print!("{}", stats),
+                OutputFormats::Json => println!("{}", stats.to_json()),
+            }
+        },
+        #[cfg(feature = "serde")]
+        Commands::Merge(merge_args) => merge(&args, merge_args),
+        Commands::Export(export_args) => {
+            let export = GraphExport {
+                min_count: export_args.min_count,
+                around: export_args.around.clone(),
+                depth: export_args.depth,
+                weights: export_args.weights.clone(),
+            };
+            let edges = trained(&args).graph_edges(&export);
+            match export_args.format {
+                GraphFormats::Dot => print!("{}", export.to_dot(&edges)),
+                GraphFormats::Graphml => print!("{}", export.to_graphml(&edges)),
+            }
+        },
+        #[cfg(feature = "serde")]
+        Commands::Prune(prune_args) => {
+            let mut mark = trained(&args);
+            let distinct = mark.transitions().len();
+            mark.prune(&Pruning {
+                min_count: prune_args.min_count,
+                min_state_count: prune_args.min_state_count,
+                top_n: prune_args.keep_top,
+                min_token_count: prune_args.min_token_count,
+            });
+            eprintln!("Kept {} of {} transitions", mark.transitions().len(), distinct);
+            print_model(&settings(&args), mark.transitions(), mark.normalizer(), &prune_args.format);
+        },
+        Commands::Repl => {
+            if reads_stdin(&args) {
+                eprintln!("repl reads its commands from stdin, so it needs --input files to train on (or a model given with --load)");
+                process::exit(1);
+            }
+            let mut repl = Repl::new(Arc::new(trained(&args).into_model()), args.max_tokens);
+            if let Err(e) = repl.run(io::stdin().lock(), io::stdout()) {
+                eprintln!("Error in repl: {}", e);
+                process::exit(1);
+            }
+        },
+        #[cfg(feature = "serde")]
+        Commands::Serve(serve_args) => {
+            let model = Arc::new(trained(&args).into_model());
+            let listener = match TcpListener::bind(&serve_args.address) {
+                Ok(listener) => listener,
+                Err(e) => {
+                    eprintln!("Could not listen on {}: {}", serve_args.address, e);
+                    process::exit(1);
+                }
+            };
+            eprintln!("Listening on http://{}", serve_args.address);
+            if let Err(e) = Server::new(model, args.max_tokens).run(listener) {
+                eprintln!("Server failed: {}", e);
+                process::exit(1);
+            }
+        },
+    }
+}
+
+/// A generator set up the way the options say, trained on the input files (or stdin) or loaded
+fn trained(args: &Args) -> MarkovGenerator {
+    let mut mark = MarkovGenerator::with_tokenizer(
+        args.boundaries.clone(), args.tokenizer.clone(), args.order
+    );
+    mark.set_encoding(args.encoding.clone());
+    mark.set_invalid_utf8(args.invalid_utf8.clone());
+    mark.set_normalization(args.normalization.clone());
+    mark.set_input_format(args.input_format.clone());
+    mark.set_strips(&args.strip);
+    if args.fold_case {
+        mark.enable_case_folding();
+    }
+    if let Commands::Generate(generate_args) = &args.command {
+        if generate_args.ends_with.is_some() || generate_args.around.is_some() {
+            mark.enable_backward();
+        }
+        if let Some(max_copy) = generate_args.max_copy {
+            mark.enable_originality_guard(max_copy);
+        }
+        if generate_args.explain {
+            mark.enable_provenance();
+        }
+    }
+
+    load_or_train(&mut mark, args);
+    mark
+}
+
+/// Exits if a beam search is asked for with options only sampling generates with, which it would
+/// otherwise ignore
+fn check_decode(generate_args: &GenerateArgs) {
+    let samples = generate_args.ends_with.is_some() || generate_args.around.is_some()
+        || !generate_args.keywords.is_empty() || generate_args.max_copy.is_some()
+        || generate_args.trace.is_some();
+    if generate_args.decode == DecodeConfigs::Beam && samples {
+        eprintln!("--decode beam can't be combined with --ends-with, --around, --keywords, --max-copy or --trace");
+        process::exit(1);
+    }
+}
+
+/// Generates and prints sentences, or a trace of generating one
+fn generate_sentences(mark: &mut MarkovGenerator, args: &Args, generate_args: &GenerateArgs) {
+    if let Some(seed) = generate_args.seed {
+        mark.seed(seed);
+    }
+    mark.set_temperature(generate_args.temperature);
+    mark.set_backoff(generate_args.backoff);
+
+    if let Some(TraceConfigs::Json) = generate_args.trace {
+        for step in mark.generate_traced(args.max_tokens) {
+            println!("{}", step.to_json());
+        }
+        return;
+    }
+
+    let outputs = match generate(mark, args.max_tokens, generate_args) {
+        Ok(outputs) => outputs,
+        Err(e) => {
+            eprintln!("Could not generate a sentence: {}", e);
+            process::exit(1);
+        }
+    };
+
+    for output in outputs {
+        println!("{}", output);
+        if let Some(originality) = mark.originality(&output) {
+            eprintln!("originality: {:.2}", originality);
+        }
+        if let Some(explained) = mark.explain(&output) {
+            print_explanation(explained);
+        }
+    }
+}
+
+/// Whether training will read from stdin
+fn reads_stdin(args: &Args) -> bool {
+    #[cfg(feature = "serde")]
+    if args.load.is_some() {
+        return false;
+    }
+
+    args.input.is_empty()
+}
 
-    let mut mark = MarkovGenerator::new(boundary_config);
-    mark.train(stdin);
+/// Trains on the input files (or stdin)
+fn train(mark: &mut MarkovGenerator, args: &Args) {
+    if let Some(bytes) = args.memory_budget {
+        mark.set_memory_budget(MemoryBudget::new(bytes));
+    }
+    if args.input.is_empty() && let Err(e) = mark.train(io::stdin().lock()) {
+        eprintln!("Could not train on stdin: {}", e);
+        process::exit(1);
+    }
+    for source in &args.input {
+        let trained = match File::open(&source.path) {
+            Ok(file) => {
+                mark.train_document(BufReader::new(file), &source.path.display().to_string(), source.weight)
+            },
+            Err(e) => {
+                eprintln!("Could not open {}: {}", source.path.display(), e);
+                process::exit(1);
+            }
+        };
+        if let Err(e) = trained {
+            eprintln!("Could not train on {}: {}", source.path.display(), e);
+            process::exit(1);
+        }
+    }
+
+    if let Some(budget) = mark.memory_budget() && budget.max_error() > 0 {
+        eprintln!("Counts kept under the memory budget can be up to {} low", budget.max_error());
+    }
+}
+
+/// Loads the model from --load and continues training it, or trains it from scratch, then saves
+/// it to --save
+#[cfg(feature = "serde")]
+fn load_or_train(mark: &mut MarkovGenerator, args: &Args) {
+    if let Some(path) = &args.load {
+        let needs_text = match &args.command {
+            Commands::Generate(generate_args) => generate_args.ends_with.is_some()
+                || generate_args.around.is_some() || generate_args.max_copy.is_some()
+                || generate_args.explain,
+            _ => false,
+        };
+        if needs_text {
+            eprintln!("--ends-with, --around, --max-copy and --explain need training, not --load");
+            process::exit(1);
+        }
+        let (transitions, normalizer) = load_model(path, &settings(args));
+        mark.set_transitions(transitions);
+        mark.set_normalizer(normalizer);
+        if let Some(factor) = args.decay {
+            mark.decay(factor);
+        }
+    }
+
+    // A loaded model only continues training on files, not stdin
+    let trains = args.load.is_none() || !args.input.is_empty();
+    if trains {
+        train(mark, args);
+    }
+    untrain(mark, args);
+    if trains || !args.untrain.is_empty() {
+        report_decoding(mark, args);
+    }
+
+    if let Some(path) = &args.save {
+        save_model(path, &settings(args), mark.transitions(), mark.normalizer());
+    }
+}
+
+#[cfg(not(feature = "serde"))]
+fn load_or_train(mark: &mut MarkovGenerator, args: &Args) {
+    train(mark, args);
+    untrain(mark, args);
+    report_decoding(mark, args);
+}
+
+/// Reports what decoding the input came across, if lines weren't valid UTF-8 or were decoded from a
+/// legacy encoding, either of which could have garbled them
+fn report_decoding(mark: &MarkovGenerator, args: &Args) {
+    let stats = mark.decode_stats();
+    if stats.invalid_lines > 0 || args.encoding != EncodingConfigs::Utf8 {
+        eprintln!("{}", stats);
+    }
+}
+
+/// Takes the --untrain files back out of the model
+fn untrain(mark: &mut MarkovGenerator, args: &Args) {
+    for source in &args.untrain {
+        let untrained = match File::open(&source.path) {
+            Ok(file) => mark.untrain(BufReader::new(file), source.weight),
+            Err(e) => {
+                eprintln!("Could not open {}: {}", source.path.display(), e);
+                process::exit(1);
+            }
+        };
+        if let Err(e) = untrained {
+            eprintln!("Could not untrain {}: {}", source.path.display(), e);
+            process::exit(1);
+        }
+    }
+}
+
+/// The options the model is trained with, which a loaded model must have been trained with too
+#[cfg(feature = "serde")]
+fn settings(args: &Args) -> ModelSettings {
+    ModelSettings {
+        order: args.order,
+        tokenizer: args.tokenizer.clone(),
+        boundaries: args.boundaries.clone(),
+        normalization: args.normalization.clone(),
+        fold_case: args.fold_case,
+    }
+}
 
-    println!("{}", mark.take(count).collect::<Vec<_>>().join(" "));
-}
\ No newline at end of file
+/// Loads a model saved with --save, exiting if it can't be or was trained with other settings
+#[cfg(feature = "serde")]
+fn load_model(path: &Path, settings: &ModelSettings) -> (Transitions, Normalizer) {
+    let loaded = fs::read_to_string(path)
+        .map_err(|e| e.to_string())
+        .and_then(|text| match is_yaml(path) {
+            true => ModelSettings::from_yaml(&text).map_err(|e| e.to_string()),
+            false => ModelSettings::from_json(&text).map_err(|e| e.to_string()),
+        })
+        .and_then(|(trained, transitions, normalizer)| {
+            trained.check(settings).map(|_| (transitions, normalizer))
+        });
+    match loaded {
+        Ok(loaded) => loaded,
+        Err(e) => {
+            eprintln!("Could not load {}: {}", path.display(), e);
+            process::exit(1);
+        }
+    }
+}
+
+/// Saves a model to a file, in the format its extension asks for, exiting if it can't be
+#[cfg(feature = "serde")]
+fn save_model(path: &Path, settings: &ModelSettings, transitions: &Transitions, normalizer: &Normalizer) {
+    let text = match is_yaml(path) {
+        true => settings.to_yaml(transitions, normalizer),
+        false => settings.to_json(transitions, normalizer),
+    };
+    if let Err(e) = fs::write(path, text) {
+        eprintln!("Could not save {}: {}", path.display(), e);
+        process::exit(1);
+    }
+}
+
+/// Prints a model to stdout
+#[cfg(feature = "serde")]
+fn print_model(
+    settings: &ModelSettings, transitions: &Transitions, normalizer: &Normalizer, format: &ModelFormats
+) {
+    match format {
+        ModelFormats::Json => println!("{}", settings.to_json(transitions, normalizer)),
+        ModelFormats::Yaml => print!("{}", settings.to_yaml(transitions, normalizer)),
+    }
+}
+
+/// Whether any options for training were given
+#[cfg(feature = "serde")]
+fn trains(args: &Args) -> bool {
+    args.load.is_some() || !args.input.is_empty() || !args.untrain.is_empty()
+}
+
+/// Merges the models given to merge, which must all have been trained with the same settings,
+/// then saves it to --save or prints it
+#[cfg(feature = "serde")]
+fn merge(args: &Args, merge_args: &MergeArgs) {
+    if trains(args) {
+        eprintln!("merge only combines models that are already trained");
+        process::exit(1);
+    }
+
+    let mut merged = Transitions::new();
+    let mut merged_normalizer = Normalizer::new(args.normalization.clone(), args.fold_case);
+    for source in &merge_args.models {
+        let (transitions, normalizer) = load_model(&source.path, &settings(args));
+        merged.merge(&transitions, source.weight);
+        merged_normalizer.merge(&normalizer);
+    }
+
+    match &args.save {
+        Some(path) => save_model(path, &settings(args), &merged, &merged_normalizer),
+        None => print_model(&settings(args), &merged, &merged_normalizer, &merge_args.format),
+    }
+}
+
+/// Generates a sentence from the mixture of the --model files
+#[cfg(feature = "serde")]
+fn generate_mixture(args: &Args, generate_args: &GenerateArgs) {
+    let decodes = generate_args.ends_with.is_some() || generate_args.around.is_some()
+        || !generate_args.keywords.is_empty() || generate_args.max_copy.is_some()
+        || generate_args.explain || generate_args.trace.is_some()
+        || generate_args.decode != DecodeConfigs::Sample;
+    if trains(args) || args.save.is_some() || decodes {
+        eprintln!("--model only generates plain sampled sentences, from models that are already trained");
+        process::exit(1);
+    }
+
+    let mut mixture = MixtureGenerator::new(args.tokenizer.clone(), args.order);
+    for source in &generate_args.models {
+        let (transitions, _) = load_model(&source.path, &settings(args));
+        mixture.add_model(transitions, source.weight);
+    }
+    if let Some(seed) = generate_args.seed {
+        mixture.seed(seed);
+    }
+    mixture.set_temperature(generate_args.temperature);
+    mixture.set_backoff(generate_args.backoff);
+
+    println!("{}", mixture.take(args.max_tokens).collect::<Vec<_>>().join(" "));
+}
+
+/// Whether a model file is YAML, going by its extension
+#[cfg(feature = "serde")]
+fn is_yaml(path: &Path) -> bool {
+    matches!(path.extension().and_then(|extension| extension.to_str()), Some("yaml" | "yml"))
+}
+
+/// Number of source locations listed for each explained token
+const EXPLAIN_LOCATIONS: usize = 5;
+
+/// Prints each token with the locations it could have come from
+fn print_explanation(explained: Vec<(String, Vec<Location>)>) {
+    for (token, locations) in explained {
+        let listed: Vec<String> = locations.iter()
+            .take(EXPLAIN_LOCATIONS)
+            .map(Location::to_string)
+            .collect();
+        let more = match locations.len().saturating_sub(EXPLAIN_LOCATIONS) {
+            0 => String::new(),
+            more => format!(" (+{} more)", more),
+        };
+        println!("  {}: {}{}", token, listed.join(", "), more);
+    }
+}
+
+/// Generates sentences, the way the args ask for
+fn generate(
+    mark: &mut MarkovGenerator, max_tokens: usize, args: &GenerateArgs
+) -> Result<Vec<String>, String> {
+    if let Some(last) = &args.ends_with {
+        return match mark.generate_ending_with(last, max_tokens) {
+            Some(sentence) => Ok(vec![sentence]),
+            None => Err(format!("no sentence ending with '{}' found", last)),
+        };
+    }
+
+    if let Some(middle) = &args.around {
+        return match mark.generate_around(middle, max_tokens) {
+            Some(sentence) => Ok(vec![sentence]),
+            None => Err(format!("no sentence around '{}' found", middle)),
+        };
+    }
+
+    if !args.keywords.is_empty() {
+        let keywords: Vec<&str> = args.keywords.iter().map(String::as_str).collect();
+        return mark.generate_with_keywords(&keywords, max_tokens, args.max_attempts)
+            .map(|sentence| vec![sentence])
+            .map_err(|e| e.to_string());
+    }
+
+    if args.decode == DecodeConfigs::Beam {
+        let beam = BeamSearch {
+            width: args.beam_width,
+            length_normalization: args.length_normalization,
+            repetition_penalty: args.repetition_penalty,
+        };
+        let sentences = mark.beam_search(&beam, &[], max_tokens, args.top_n);
+        return Ok(sentences.into_iter().map(|(sentence, _)| sentence).collect());
+    }
+
+    if args.max_copy.is_some() {
+        return mark.generate(max_tokens, args.max_attempts)
+            .map(|sentence| vec![sentence])
+            .map_err(|e| e.to_string());
+    }
+
+    Ok(vec![mark.take(max_tokens).collect::<Vec<_>>().join(" ")])
+}
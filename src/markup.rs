@@ -0,0 +1,507 @@
+//! markup module
+//!
+//! Contains the extraction of the visible text of HTML and Markdown input, block by block, before
+//! it is tokenized
+
+use crate::error::Error;
+use crate::InputFormats;
+
+
+/// HTML elements whose contents aren't visible text
+const HIDDEN_ELEMENTS: [&str; 4] = ["script", "style", "noscript", "template"];
+
+/// HTML elements that start or end a block of text, like a paragraph or a list item
+const BLOCK_ELEMENTS: [&str; 33] = [
+    "address", "article", "aside", "blockquote", "br", "caption", "dd", "div", "dl", "dt",
+    "figcaption", "figure", "footer", "form", "h1", "h2", "h3", "h4", "h5", "h6", "header", "hr",
+    "li", "main", "nav", "ol", "p", "pre", "section", "td", "th", "title", "tr",
+];
+
+/// HTML character references that aren't numeric, and what they stand for
+const ENTITIES: [(&str, &str); 14] = [
+    ("amp", "&"), ("lt", "<"), ("gt", ">"), ("quot", "\""), ("apos", "'"), ("nbsp", " "),
+    ("mdash", "—"), ("ndash", "–"), ("hellip", "…"), ("lsquo", "‘"), ("rsquo", "’"),
+    ("ldquo", "“"), ("rdquo", "”"), ("copy", "©"),
+];
+
+/// Longest character reference, between its & and ;
+const MAX_ENTITY: usize = 10;
+
+/// Extracts the visible text of a document, in HTML or Markdown, as it reads its lines
+///
+/// The text of each block, like a heading, paragraph or list item, is given on the line it ends
+/// on, followed by a line break, which tokenizing takes as a boundary. Lines that don't end a
+/// block are read as empty lines, so the lines after them keep their line numbers. From HTML, the
+/// contents of `script` and `style` elements and comments are dropped, along with tags, and
+/// character references are decoded. From Markdown, fenced code blocks, link URLs and reference
+/// definitions, and formatting markers like `**` and `#` are dropped. Text input is left alone.
+///
+/// # Examples
+/// ```rust
+/// use rusty_markov::{Extractor, InputFormats};
+///
+/// let mut extractor = Extractor::new(&InputFormats::Markdown);
+/// assert_eq!(extractor.extract("# The **Whale**"), "The Whale\n");
+/// assert_eq!(extractor.extract("Call me [Ishmael](https://example.com)."), "");
+/// assert_eq!(extractor.finish(), "Call me Ishmael.\n");
+/// ```
+#[derive(Debug, Clone)]
+pub struct Extractor {
+    format: InputFormats,
+    /// Text of the block read so far
+    block: String,
+    /// In Markdown, the marker of the fenced code block being read
+    fence: Option<String>,
+    /// In Markdown, whether the last line was quoted
+    quoted: bool,
+    /// In HTML, the tag or comment being read, after its <
+    tag: Option<String>,
+    /// In HTML, the hidden element being read
+    hidden: Option<String>,
+}
+
+impl Extractor {
+    /// Construct an extractor for the start of a document in the format given
+    pub fn new(format: &InputFormats) -> Self {
+        Self {
+            format: format.clone(),
+            block: String::new(),
+            fence: None,
+            quoted: false,
+            tag: None,
+            hidden: None,
+        }
+    }
+
+    /// Extract the text of the lines of a document, passing errors through. The last block is
+    /// given on the last line.
+    pub fn extract_lines<I: Iterator<Item = Result<String, Error>>>(
+        mut self, lines: I
+    ) -> impl Iterator<Item = Result<String, Error>> {
+        let mut lines = lines.peekable();
+        std::iter::from_fn(move || {
+            let line = lines.next()?;
+            let last = lines.peek().is_none();
+            Some(line.map(|line| {
+                let mut text = self.extract(&line);
+                if last {
+                    text.push_str(&self.finish());
+                }
+                text
+            }))
+        })
+    }
+
+    /// Read the next line of the document, returning the text of the blocks it ends
+    pub fn extract(&mut self, line: &str) -> String {
+        match self.format {
+            InputFormats::Text => line.to_string(),
+            InputFormats::Html => self.extract_html(line),
+            InputFormats::Markdown => self.extract_markdown(line),
+        }
+    }
+
+    /// Finish the document, returning the text of the block it ends in, if there is one
+    pub fn finish(&mut self) -> String {
+        let mut ended = String::new();
+        self.end_block(&mut ended);
+        self.fence = None;
+        self.quoted = false;
+        self.tag = None;
+        self.hidden = None;
+
+        ended
+    }
+
+    /// Add the block read so far to the text of the ended blocks, if it has any text
+    fn end_block(&mut self, ended: &mut String) {
+        let text = match self.format {
+            InputFormats::Markdown => strip_inline(&self.block),
+            _ => collapse_whitespace(&self.block),
+        };
+        if !text.is_empty() {
+            ended.push_str(&text);
+            ended.push('\n');
+        }
+        self.block.clear();
+    }
+
+    fn extract_html(&mut self, line: &str) -> String {
+        let mut ended = String::new();
+        let chars: Vec<char> = line.chars().collect();
+        let mut i = 0;
+
+        while i < chars.len() {
+            let c = chars[i];
+            if let Some(tag) = &mut self.tag {
+                let comment = tag.starts_with("!--");
+                if c == '>' && (!comment || tag.ends_with("--")) {
+                    let tag = self.tag.take().unwrap_or_default();
+                    self.read_tag(&tag, &mut ended);
+                } else {
+                    tag.push(c);
+                }
+            } else if c == '<' && starts_tag(chars.get(i + 1).copied(), self.hidden.is_some()) {
+                self.tag = Some(String::new());
+            } else if self.hidden.is_none() {
+                match c {
+                    '&' => match decode_entity(&chars[i + 1..]) {
+                        Some((text, length)) => {
+                            self.block.push_str(&text);
+                            i += length;
+                        },
+                        None => self.block.push(c),
+                    },
+                    _ => self.block.push(c),
+                }
+            }
+            i += 1;
+        }
+
+        // A line break is whitespace, in text and in tags
+        match &mut self.tag {
+            Some(tag) => tag.push(' '),
+            None => self.block.push(' '),
+        }
+
+        ended
+    }
+
+    /// Act on a tag read in full, without its < and >
+    fn read_tag(&mut self, tag: &str, ended: &mut String) {
+        if tag.starts_with('!') || tag.starts_with('?') {
+            return;
+        }
+        let closing = tag.starts_with('/');
+        let name = tag.trim_start_matches('/')
+            .split(|c: char| c.is_whitespace() || c == '/')
+            .next()
+            .unwrap_or_default()
+            .to_lowercase();
+
+        if let Some(hidden) = &self.hidden {
+            if closing && *hidden == name {
+                self.hidden = None;
+            }
+            return;
+        }
+        if !closing && !tag.ends_with('/') && HIDDEN_ELEMENTS.contains(&name.as_str()) {
+            self.hidden = Some(name);
+        } else if BLOCK_ELEMENTS.contains(&name.as_str()) {
+            self.end_block(ended);
+        }
+    }
+
+    fn extract_markdown(&mut self, line: &str) -> String {
+        let mut ended = String::new();
+        let trimmed = line.trim();
+
+        if let Some(fence) = &self.fence {
+            if trimmed.starts_with(fence.as_str()) && trimmed.chars().all(|c| fence.starts_with(c)) {
+                self.fence = None;
+            }
+            return ended;
+        }
+        if trimmed.starts_with("```") || trimmed.starts_with("~~~") {
+            let marker = trimmed.chars().next().unwrap_or_default();
+            self.fence = Some(trimmed.chars().take_while(|c| *c == marker).collect());
+            self.end_block(&mut ended);
+            return ended;
+        }
+
+        let mut text = trimmed;
+        while let Some(quoted) = text.strip_prefix('>') {
+            text = quoted.trim_start();
+        }
+        // A quote starts a block of its own
+        if (text.len() < trimmed.len()) != self.quoted {
+            self.quoted = !self.quoted;
+            self.end_block(&mut ended);
+        }
+
+        if text.is_empty() || is_break(text) || is_table_separator(text) || is_reference_definition(text) {
+            self.end_block(&mut ended);
+        } else if let Some(heading) = heading_text(text) {
+            self.end_block(&mut ended);
+            self.block.push_str(heading);
+            self.end_block(&mut ended);
+        } else if text.starts_with('|') {
+            self.end_block(&mut ended);
+            self.block.push_str(&text.replace('|', " "));
+            self.end_block(&mut ended);
+        } else if let Some(item) = list_item_text(text) {
+            self.end_block(&mut ended);
+            self.block.push_str(item);
+        } else {
+            self.block.push(' ');
+            self.block.push_str(text);
+        }
+
+        ended
+    }
+}
+
+/// Whether a < followed by this character starts a tag, rather than being text like `a < b`.
+/// In a hidden element, like a script, only closing tags are looked for.
+fn starts_tag(next: Option<char>, hidden: bool) -> bool {
+    match next {
+        Some('/') => true,
+        Some(c) => !hidden && (c.is_ascii_alphabetic() || c == '!' || c == '?'),
+        None => false,
+    }
+}
+
+/// Decode the character reference after an &, returning its text and how many characters it
+/// took up, with its ;
+fn decode_entity(chars: &[char]) -> Option<(String, usize)> {
+    let end = chars.iter().take(MAX_ENTITY + 1).position(|c| *c == ';')?;
+    let name: String = chars[..end].iter().collect();
+
+    let text = match name.strip_prefix('#') {
+        Some(number) => {
+            let code = match number.strip_prefix(['x', 'X']) {
+                Some(hex) => u32::from_str_radix(hex, 16).ok()?,
+                None => number.parse().ok()?,
+            };
+            char::from_u32(code)?.to_string()
+        },
+        None => ENTITIES.iter().find(|(entity, _)| *entity == name)?.1.to_string(),
+    };
+
+    Some((text, end + 1))
+}
+
+/// Collapse runs of whitespace into single spaces, and trim it from the ends
+fn collapse_whitespace(text: &str) -> String {
+    text.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+/// The text of a Markdown ATX heading, like `## Loomings ##`
+fn heading_text(text: &str) -> Option<&str> {
+    let level = text.chars().take_while(|c| *c == '#').count();
+    let rest = &text[level..];
+    if level == 0 || level > 6 || !(rest.is_empty() || rest.starts_with(' ')) {
+        return None;
+    }
+
+    Some(rest.trim().trim_end_matches('#').trim_end())
+}
+
+/// The text of a Markdown list item, like `- item`, `1. item` or `* [x] item`
+fn list_item_text(text: &str) -> Option<&str> {
+    let digits = text.chars().take_while(|c| c.is_ascii_digit()).count();
+    let item = match digits {
+        0 => text.strip_prefix(['-', '*', '+'])?,
+        _ => text[digits..].strip_prefix(['.', ')'])?,
+    };
+    if !item.starts_with(' ') {
+        return None;
+    }
+
+    let item = item.trim_start();
+    for task in ["[ ] ", "[x] ", "[X] "] {
+        if let Some(rest) = item.strip_prefix(task) {
+            return Some(rest);
+        }
+    }
+    Some(item)
+}
+
+/// Whether a Markdown line is a thematic break, like `***`, or the underline of a heading
+fn is_break(text: &str) -> bool {
+    let marks: Vec<char> = text.chars().filter(|c| !c.is_whitespace()).collect();
+    match marks.first() {
+        Some('=') => marks.iter().all(|c| *c == '='),
+        Some(mark @ ('-' | '*' | '_')) => marks.len() >= 3 && marks.iter().all(|c| c == mark),
+        _ => false,
+    }
+}
+
+/// Whether a Markdown line separates the header of a table from its rows, like `|---|:--:|`
+fn is_table_separator(text: &str) -> bool {
+    text.contains('|') && text.contains('-') && text.chars().all(|c| "|-: ".contains(c))
+}
+
+/// Whether a Markdown line defines the URL of a reference link, like `[1]: https://example.com`
+fn is_reference_definition(text: &str) -> bool {
+    text.starts_with('[') && !text.starts_with("[^")
+        && text.split_once("]:").is_some_and(|(_, url)| !url.trim().contains(' '))
+}
+
+/// Strip the inline formatting of Markdown text, keeping the text of links and images and of
+/// code spans, and dropping URLs, footnote references, HTML tags and emphasis markers
+fn strip_inline(text: &str) -> String {
+    let chars: Vec<char> = text.chars().collect();
+    let mut stripped = String::with_capacity(text.len());
+    let mut i = 0;
+
+    while i < chars.len() {
+        let c = chars[i];
+        match c {
+            '\\' if chars.get(i + 1).is_some_and(|c| c.is_ascii_punctuation()) => {
+                stripped.push(chars[i + 1]);
+                i += 2;
+            },
+            '`' => {
+                let run = run_length(&chars, i);
+                let close = (i + run..chars.len()).find(|&j| run_length(&chars, j) == run && chars[j] == '`');
+                match close {
+                    Some(close) => {
+                        stripped.extend(&chars[i + run..close]);
+                        i = close + run;
+                    },
+                    None => i += run,
+                }
+            },
+            '[' | '!' if c == '[' || chars.get(i + 1) == Some(&'[') => {
+                let start = if c == '!' { i + 1 } else { i };
+                match link_text(&chars, start) {
+                    Some((text, end)) => {
+                        stripped.push_str(&strip_inline(&text));
+                        i = end;
+                    },
+                    None => {
+                        stripped.push(c);
+                        i += 1;
+                    },
+                }
+            },
+            '<' if starts_tag(chars.get(i + 1).copied(), false) => {
+                match chars[i..].iter().position(|c| *c == '>') {
+                    Some(end) => i += end + 1,
+                    None => {
+                        stripped.push(c);
+                        i += 1;
+                    },
+                }
+            },
+            '*' | '_' | '~' => {
+                let run = run_length(&chars, i);
+                let before = i.checked_sub(1).map(|j| chars[j]);
+                let after = chars.get(i + run).copied();
+                if !is_emphasis(c, run, before, after) {
+                    stripped.extend(&chars[i..i + run]);
+                }
+                i += run;
+            },
+            _ => {
+                stripped.push(c);
+                i += 1;
+            },
+        }
+    }
+
+    collapse_whitespace(&stripped)
+}
+
+/// How many of the character at i there are in a row from i
+fn run_length(chars: &[char], i: usize) -> usize {
+    chars[i..].iter().take_while(|c| **c == chars[i]).count()
+}
+
+/// Whether a run of `*`, `_` or `~` is an emphasis (or strikethrough) marker, rather than text
+/// like `snake_case`, `~5 km` or `2 * 3`
+fn is_emphasis(mark: char, run: usize, before: Option<char>, after: Option<char>) -> bool {
+    let opens = after.is_some_and(|c| !c.is_whitespace());
+    let closes = before.is_some_and(|c| !c.is_whitespace());
+    match mark {
+        '~' => run >= 2 && (opens || closes),
+        '_' => {
+            (opens && !before.is_some_and(char::is_alphanumeric))
+                || (closes && !after.is_some_and(char::is_alphanumeric))
+        },
+        _ => opens || closes,
+    }
+}
+
+/// The text of a link starting at the [ at start, like `[text](url)`, `[text][ref]`, `[text]`
+/// or a wiki link like `[[Page|text]]`, and where the link ends. Footnote references, like
+/// `[^1]`, have no text.
+fn link_text(chars: &[char], start: usize) -> Option<(String, usize)> {
+    if chars.get(start + 1) == Some(&'[') {
+        let inner = start + 2;
+        let end = (inner..chars.len()).find(|&j| chars[j] == ']' && chars.get(j + 1) == Some(&']'))?;
+        let page: String = chars[inner..end].iter().collect();
+        let text = page.rsplit('|').next().unwrap_or_default().to_string();
+        return Some((text, end + 2));
+    }
+
+    let mut depth = 0;
+    let close = (start..chars.len()).find(|&j| {
+        match chars[j] {
+            '[' => depth += 1,
+            ']' => depth -= 1,
+            _ => (),
+        }
+        depth == 0
+    })?;
+    let text: String = chars[start + 1..close].iter().collect();
+    if text.starts_with('^') {
+        return Some((String::new(), close + 1));
+    }
+
+    let end = match chars.get(close + 1) {
+        Some('(') => close + 2 + chars[close + 2..].iter().position(|c| *c == ')')? + 1,
+        Some('[') => close + 2 + chars[close + 2..].iter().position(|c| *c == ']')? + 1,
+        _ => close + 1,
+    };
+    Some((text, end))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn extract(format: InputFormats, text: &str) -> Vec<String> {
+        let extractor = Extractor::new(&format);
+        extractor.extract_lines(text.lines().map(|line| Ok(line.to_string())))
+            .map(Result::unwrap)
+            .collect()
+    }
+
+    #[test]
+    fn test_extract_html() {
+        let text = "<html><head><title>Moby Dick</title>\n<style>p { color: red; }</style>\n\
+            <script>if (a<b) { go(\"</p>\"); }\n</script></head><body>\n\
+            <h1>Loomings</h1><p>Call me <b>Ishmael</b>. Some years ago&mdash;never\n\
+            mind how long&#8212;precisely &amp; <!-- a <p> comment --> having\n\
+            <a\nhref=\"/money\">little</a> money &lt;</p>\n<ul><li>one</li><li>two &x; <br>three</ul></body></html>";
+
+        assert_eq!(extract(InputFormats::Html, text), vec![
+            "Moby Dick\n", "", "", "",
+            "Loomings\n",
+            "",
+            "",
+            "Call me Ishmael. Some years ago—never mind how long—precisely & having little money <\n",
+            "one\ntwo &x;\nthree\n",
+        ]);
+    }
+
+    #[test]
+    fn test_extract_markdown() {
+        let text = "Title\n=====\n\n## Loomings ##\nCall me **Ishmael**, _the_ narrator of\n\
+            [Moby Dick](https://example.com \"book\") ![whale](whale.png) and [[Wiki Page|wikis]][^1].\n\
+            ```rust\nlet x = **y**;\n```\n- [x] a `snake_case` item\n1. ~~struck~~ ~5 km, 2 * 3\n\
+            > quoted <br> text <https://example.com>\n\n| a | b |\n|---|:-:|\n[1]: https://example.com\n\\*not emphasis\\*";
+
+        assert_eq!(extract(InputFormats::Markdown, text), vec![
+            "", "Title\n", "", "Loomings\n", "", "",
+            "Call me Ishmael, the narrator of Moby Dick whale and wikis.\n",
+            "", "", "",
+            "a snake_case item\n",
+            "struck ~5 km, 2 * 3\n",
+            "quoted text\n",
+            "a b\n",
+            "", "",
+            "*not emphasis*\n",
+        ]);
+    }
+
+    #[test]
+    fn test_extract_text_unchanged() {
+        let text = "**not** <b>markup</b>\n\n# here";
+
+        assert_eq!(extract(InputFormats::Text, text), text.lines().collect::<Vec<_>>());
+    }
+}
@@ -0,0 +1,249 @@
+//! mixture module
+//!
+//! Contains a generator mixing the transitions of several models
+
+use std::collections::HashMap;
+use rand::SeedableRng;
+use rand::rngs::StdRng;
+
+use crate::chain::sample_token;
+use crate::context::Context;
+use crate::generator::{next_word, states_to_try};
+use crate::normalize::Normalizer;
+use crate::token::Token;
+use crate::transitions::Transitions;
+use crate::TokenizerConfigs;
+
+
+/// Generates text from several trained models at once, such as one per author or genre, each
+/// with a mixing weight
+///
+/// At each step, the successor probabilities each model gives the current state are combined in
+/// proportion to the mixing weights. Models that don't know the state are left out of the mix,
+/// and the weights of the rest are scaled up to make up for them. When no model knows the state,
+/// the sentence ends there, or with backoff, it backs off to shorter parts of the history the way
+/// `MarkovGenerator` does.
+///
+/// # Examples
+/// ```rust
+/// use std::io::Cursor;
+/// use rusty_markov::{train_with_stream, BoundaryConfigs, MixtureGenerator, TokenizerConfigs, Transitions};
+///
+/// let mut cats = Transitions::new();
+/// train_with_stream(Cursor::new("the cat sat"), &mut cats, &BoundaryConfigs::LineEndings).unwrap();
+/// let mut dogs = Transitions::new();
+/// train_with_stream(Cursor::new("a dog ran"), &mut dogs, &BoundaryConfigs::LineEndings).unwrap();
+///
+/// let mut mixture = MixtureGenerator::new(TokenizerConfigs::Words, 1);
+/// mixture.add_model(cats, 3.0);
+/// mixture.add_model(dogs, 1.0);
+///
+/// let sentence: Vec<String> = mixture.take(5).collect();
+/// assert!(sentence == ["the", "cat", "sat"] || sentence == ["a", "dog", "ran"]);
+/// ```
+pub struct MixtureGenerator {
+    tokenizer_config: TokenizerConfigs,
+    models: Vec<(Transitions, f64)>,
+    context: Context,
+    rng: StdRng,
+    temperature: f64,
+    backoff: bool,
+}
+
+impl MixtureGenerator {
+    /// Construct a mixture with no models yet, for models trained with this tokenizer and chain
+    /// order
+    pub fn new(tokenizer_config: TokenizerConfigs, order: usize) -> Self {
+        let context = Context::new(order, &tokenizer_config);
+        Self {
+            tokenizer_config,
+            models: Vec::new(),
+            context,
+            rng: StdRng::from_os_rng(),
+            temperature: 1.0,
+            backoff: false,
+        }
+    }
+
+    /// Add a model to the mix, with a positive mixing weight. Only the ratios between the weights
+    /// matter.
+    ///
+    /// # Panics
+    /// If the weight isn't a positive number
+    pub fn add_model(&mut self, transitions: Transitions, weight: f64) {
+        assert!(weight > 0.0 && weight.is_finite(), "mixing weight {} isn't a positive number", weight);
+        self.models.push((transitions, weight));
+    }
+
+    /// Restart the random number generator from a seed, so what is generated from here on can
+    /// be repeated
+    pub fn seed(&mut self, seed: u64) {
+        self.rng = StdRng::seed_from_u64(seed);
+    }
+
+    /// Set the temperature tokens are picked from the combined probabilities with, 1 (the
+    /// default) picks them in proportion to their probabilities
+    pub fn set_temperature(&mut self, temperature: f64) {
+        self.temperature = temperature;
+    }
+
+    /// Back off to shorter parts of the history when no model knows the current state, instead
+    /// of ending the sentence there. Off to start with.
+    pub fn set_backoff(&mut self, backoff: bool) {
+        self.backoff = backoff;
+    }
+
+    /// The models in the mix, with their mixing weights
+    pub fn models(&self) -> &[(Transitions, f64)] {
+        &self.models
+    }
+
+    /// The combined probabilities of the tokens following a state, or None if no model knows it
+    ///
+    /// Each model's counts are turned into probabilities before mixing, so a model trained on a
+    /// large corpus doesn't drown out the others beyond its mixing weight
+    pub fn next_probabilities(&self, state: &Token) -> Option<HashMap<Token, f64>> {
+        let mut mixed: HashMap<Token, f64> = HashMap::new();
+        let mut total_weight = 0.0;
+
+        for (transitions, weight) in &self.models {
+            let Some(next_tokens) = transitions.next_tokens(state) else {
+                continue;
+            };
+            let total: f64 = next_tokens.values().sum();
+            for (token, count) in next_tokens {
+                *mixed.entry(token.clone()).or_default() += weight * count / total;
+            }
+            total_weight += weight;
+        }
+
+        if mixed.is_empty() {
+            return None;
+        }
+
+        // Scale up for the models that were left out
+        for probability in mixed.values_mut() {
+            *probability /= total_weight;
+        }
+        Some(mixed)
+    }
+
+    /// Pick the next token and shift it into the context
+    ///
+    /// If no model knows the current state, starts over from a Token::Boundary, or with backoff,
+    /// backs off to shorter parts of the history first.
+    fn advance(&mut self) -> Token {
+        for state in states_to_try(&self.context, self.backoff) {
+            let Some(mixed) = self.next_probabilities(&state) else {
+                continue;
+            };
+            let Ok(next_token) = sample_token(&mixed, self.temperature, &mut self.rng) else {
+                continue;
+            };
+
+            let next_token = next_token.clone();
+            self.context.push(&next_token);
+            return next_token;
+        }
+
+        self.context.push(&Token::Boundary);
+        Token::Boundary
+    }
+}
+
+impl Iterator for MixtureGenerator {
+    type Item = String;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        // Models are mixed as they were saved, without the casing of their training text
+        let tokenizer_config = self.tokenizer_config.clone();
+        next_word(&tokenizer_config, &Normalizer::default(), false, || self.advance())
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+    use super::*;
+    use crate::train::train_with_tokenizer;
+    use crate::BoundaryConfigs;
+    use crate::decode::Decoder;
+
+    fn model(text: &str, order: usize) -> Transitions {
+        let mut transitions = Transitions::new();
+        train_with_tokenizer(
+            Cursor::new(text),
+            &mut transitions,
+            &BoundaryConfigs::LineEndings,
+            &TokenizerConfigs::Words,
+            order,
+            &mut Decoder::default(),
+        ).unwrap();
+        transitions
+    }
+
+    #[test]
+    fn test_mixture_combines_distributions() {
+        let mut mixture = MixtureGenerator::new(TokenizerConfigs::Words, 1);
+        // The second model's larger counts don't count for more than its weight
+        mixture.add_model(model("the cat", 1), 3.0);
+        mixture.add_model(model("the dog\nthe dog\nthe bird\nthe bird\nanother", 1), 1.0);
+
+        let mixed = mixture.next_probabilities(&Token::from("the")).unwrap();
+        assert_eq!(mixed.len(), 3);
+        assert!((mixed[&Token::from("cat")] - 0.75).abs() < 1e-9);
+        assert!((mixed[&Token::from("dog")] - 0.125).abs() < 1e-9);
+        assert!((mixed[&Token::from("bird")] - 0.125).abs() < 1e-9);
+
+        // Only the second model knows "another", so it gets all the weight
+        let mixed = mixture.next_probabilities(&Token::from("another")).unwrap();
+        assert_eq!(mixed, HashMap::from([(Token::Boundary, 1.0)]));
+
+        assert_eq!(mixture.next_probabilities(&Token::from("unknown")), None);
+
+        // Raised to a high power, every probability would underflow to 0 without rescaling
+        mixture.set_temperature(0.0001);
+        mixture.context.push(&Token::from("the"));
+        assert_eq!(mixture.advance(), Token::from("cat"));
+    }
+
+    #[test]
+    #[should_panic(expected = "isn't a positive number")]
+    fn test_mixture_rejects_nonpositive_weights() {
+        MixtureGenerator::new(TokenizerConfigs::Words, 1).add_model(model("the cat", 1), 0.0);
+    }
+
+    #[test]
+    fn test_mixture_falls_back_across_models() {
+        let mut mixture = MixtureGenerator::new(TokenizerConfigs::Words, 2);
+        mixture.add_model(model("one two three", 2), 1.0);
+        mixture.add_model(model("three four five", 2), 1.0);
+
+        // Each state is known to only one of the models, so each sentence follows just one of them
+        for _ in 0..20 {
+            let sentence: Vec<String> = mixture.by_ref().take(10).collect();
+            assert!(
+                sentence == ["one", "two", "three"] || sentence == ["three", "four", "five"],
+                "Unexpected sentence {:?}", sentence
+            );
+        }
+    }
+
+    #[test]
+    fn test_mixture_backs_off() {
+        let mut mixture = MixtureGenerator::new(TokenizerConfigs::Words, 2);
+        mixture.add_model(model("b c", 2), 1.0);
+        mixture.add_model(model("x b d", 2), 1.0);
+
+        // Neither model knows the state "a b", so it backs off to "b", which only the first knows
+        mixture.context.push(&Token::from("a"));
+        mixture.context.push(&Token::from("b"));
+        assert_eq!(mixture.advance(), Token::Boundary, "Should only back off when asked to");
+
+        mixture.set_backoff(true);
+        mixture.context.push(&Token::from("a"));
+        mixture.context.push(&Token::from("b"));
+        assert_eq!(mixture.advance(), Token::from("c"));
+    }
+}
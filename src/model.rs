@@ -0,0 +1,409 @@
+//! model module
+//!
+//! Contains a trained model that can be shared between threads, and the lightweight generators
+//! that each thread generates from it with
+
+use std::sync::Arc;
+use rand::SeedableRng;
+use rand::rngs::StdRng;
+
+use crate::chain::walk_backward;
+use crate::context::Context;
+use crate::generator::{next_state, next_word, split, step};
+use crate::normalize::Normalizer;
+use crate::score::SentenceScore;
+use crate::stats::ModelStats;
+use crate::token::Token;
+use crate::tokenize::ends_sentence;
+use crate::trace::TraceStep;
+use crate::transitions::Transitions;
+use crate::TokenizerConfigs;
+
+
+/// Trained transitions that don't change any more, so they can be shared between threads in an
+/// `Arc` instead of being copied or retrained for each of them
+///
+/// Generating is done by a `GeneratorHandle`, which keeps its own random number generator and
+/// context and only points at the model, so there can be as many of them as there are callers.
+///
+/// # Examples
+/// ```rust
+/// use std::io::Cursor;
+/// use std::sync::Arc;
+/// use std::thread;
+/// use rusty_markov::{MarkovGenerator, BoundaryConfigs};
+///
+/// let mut generator = MarkovGenerator::new(BoundaryConfigs::LineEndings);
+/// generator.train(Cursor::new("start middle end")).unwrap();
+/// let model = Arc::new(generator.into_model());
+///
+/// let threads: Vec<_> = (0..4)
+///     .map(|_| {
+///         let mut handle = model.generator();
+///         thread::spawn(move || handle.generate_from("", 10))
+///     })
+///     .collect();
+/// for thread in threads {
+///     assert_eq!(thread.join().unwrap(), "start middle end");
+/// }
+/// ```
+#[derive(Debug)]
+pub struct MarkovModel {
+    tokenizer_config: TokenizerConfigs,
+    order: usize,
+    transitions: Transitions,
+    normalizer: Normalizer,
+}
+
+impl MarkovModel {
+    /// Construct a model from transitions trained with this tokenizer and chain order
+    pub fn new(tokenizer_config: TokenizerConfigs, order: usize, transitions: Transitions) -> Self {
+        Self {
+            tokenizer_config,
+            order,
+            transitions,
+            normalizer: Normalizer::default(),
+        }
+    }
+
+    /// Normalise text given to the model the way its training text was, and restore the casing
+    /// of what is generated from it
+    pub fn with_normalizer(mut self, normalizer: Normalizer) -> Self {
+        self.normalizer = normalizer;
+        self
+    }
+
+    /// A new generator for this model, with a random number generator seeded from the operating
+    /// system
+    pub fn generator(self: &Arc<Self>) -> GeneratorHandle {
+        GeneratorHandle::new(Arc::clone(self), StdRng::from_os_rng())
+    }
+
+    /// A new generator for this model, seeded so what it generates can be repeated
+    pub fn seeded_generator(self: &Arc<Self>, seed: u64) -> GeneratorHandle {
+        GeneratorHandle::new(Arc::clone(self), StdRng::seed_from_u64(seed))
+    }
+
+    /// The trained transitions
+    pub fn transitions(&self) -> &Transitions {
+        &self.transitions
+    }
+
+    /// The tokenizer the transitions were trained with
+    pub fn tokenizer_config(&self) -> &TokenizerConfigs {
+        &self.tokenizer_config
+    }
+
+    /// The chain order the transitions were trained with
+    pub fn order(&self) -> usize {
+        self.order
+    }
+
+    /// The trained transitions, for a `MarkovGenerator` to keep training
+    pub(crate) fn transitions_mut(&mut self) -> &mut Transitions {
+        &mut self.transitions
+    }
+
+    /// The normalizer text given to the model is normalised with
+    pub(crate) fn normalizer(&self) -> &Normalizer {
+        &self.normalizer
+    }
+
+    /// The normalizer, for a `MarkovGenerator` to keep counting the casing of training tokens
+    pub(crate) fn normalizer_mut(&mut self) -> &mut Normalizer {
+        &mut self.normalizer
+    }
+
+    /// How likely a sentence is to be generated
+    pub fn score(&self, sentence: &str) -> SentenceScore {
+        let context = Context::new(self.order, &self.tokenizer_config);
+        SentenceScore::from_tokens(&self.transitions, &self.split(sentence), context)
+    }
+
+    /// The tokens that could follow the start of a sentence, most likely first, with their
+    /// probabilities
+    ///
+    /// The state they follow is returned too, which is shorter than the end of the text if the
+    /// model had to back off to find one it knows. Returns None if it knows none.
+    pub fn successors(&self, text: &str) -> Option<(Token, Vec<(Token, f64)>)> {
+        let mut context = Context::new(self.order, &self.tokenizer_config);
+        for value in self.split(text) {
+            context.push(&Token::from(value));
+        }
+
+        context.backoff_states().into_iter().find_map(|state| {
+            let next_tokens = self.transitions.next_tokens(&state)?;
+            let total: f64 = next_tokens.values().sum();
+            let mut successors: Vec<(Token, f64)> = next_tokens.iter()
+                .map(|(token, count)| (token.clone(), count / total))
+                .collect();
+            successors.sort_by(|(a_token, a), (b_token, b)| {
+                b.total_cmp(a).then_with(|| a_token.cmp(b_token))
+            });
+            Some((state, successors))
+        })
+    }
+
+    /// Split text into tokens, normalised the way the training tokens were
+    pub(crate) fn split(&self, text: &str) -> Vec<String> {
+        split(&self.tokenizer_config, &self.normalizer.fold(text))
+    }
+
+    /// Join the tokens of a generated sentence into text, restoring their casing
+    pub(crate) fn join(&self, tokens: &[String]) -> String {
+        Context::new(self.order, &self.tokenizer_config).join(&self.normalizer.restore_sentence(tokens))
+    }
+
+    /// The state following a state once token is picked from it
+    pub(crate) fn next_state(&self, state: &Token, token: &Token) -> Token {
+        next_state(&self.tokenizer_config, self.order, state, token)
+    }
+
+    /// Summary statistics of the trained transitions, listing the top_n of the top lists
+    pub fn stats(&self, top_n: usize) -> ModelStats {
+        ModelStats::from_transitions(&self.transitions, top_n, |state, token| self.next_state(state, token))
+    }
+}
+
+/// Generates text from a shared `MarkovModel`, with its own random number generator, temperature
+/// and context
+///
+/// Handles are cheap to make, and can be sent to other threads.
+pub struct GeneratorHandle {
+    model: Arc<MarkovModel>,
+    context: Context,
+    rng: StdRng,
+    temperature: f64,
+    backoff: bool,
+    /// Whether the next word starts a sentence, for the tokenizers that generate a word at a time
+    sentence_start: bool,
+}
+
+impl GeneratorHandle {
+    fn new(model: Arc<MarkovModel>, rng: StdRng) -> Self {
+        let context = Context::new(model.order, &model.tokenizer_config);
+        Self {
+            model,
+            context,
+            rng,
+            temperature: 1.0,
+            backoff: false,
+            sentence_start: true,
+        }
+    }
+
+    /// The model being generated from
+    pub fn model(&self) -> &Arc<MarkovModel> {
+        &self.model
+    }
+
+    /// The model, for a `MarkovGenerator` to keep training
+    ///
+    /// A `MarkovGenerator` never shares the model of its handle, so the handle is the only owner
+    /// of it.
+    pub(crate) fn model_mut(&mut self) -> &mut MarkovModel {
+        Arc::get_mut(&mut self.model).expect("a generator's model isn't shared")
+    }
+
+    /// Take the model out of the handle, if no other handle shares it
+    pub(crate) fn into_model(self) -> Option<MarkovModel> {
+        Arc::into_inner(self.model)
+    }
+
+    /// Restart the random number generator from a seed, so what is generated from here on can
+    /// be repeated
+    pub fn seed(&mut self, seed: u64) {
+        self.rng = StdRng::seed_from_u64(seed);
+    }
+
+    /// Set the temperature tokens are picked with, 1 (the default) picks them in proportion to
+    /// their counts
+    pub fn set_temperature(&mut self, temperature: f64) {
+        self.temperature = temperature;
+    }
+
+    /// The temperature tokens are picked with
+    pub fn temperature(&self) -> f64 {
+        self.temperature
+    }
+
+    /// Back off to shorter parts of the history when the current state is unknown, instead of
+    /// ending the sentence there. Off to start with.
+    pub fn set_backoff(&mut self, backoff: bool) {
+        self.backoff = backoff;
+    }
+
+    /// Generate one sentence continuing on from a prompt, with up to max_tokens more tokens
+    ///
+    /// The prompt is taken as the start of a sentence, and is included in what is returned. An
+    /// empty prompt generates a sentence from the start.
+    pub fn generate_from(&mut self, prompt: &str, max_tokens: usize) -> String {
+        let mut tokens = self.model.split(prompt);
+        self.continue_sentence(&mut tokens, max_tokens);
+
+        self.model.join(&tokens)
+    }
+
+    /// Generate one whole sentence (or word) of up to max_tokens, recording each step of it
+    ///
+    /// The last step picks the Token::Boundary ending the sentence, unless it was cut off at
+    /// max_tokens
+    pub fn generate_traced(&mut self, max_tokens: usize) -> Vec<TraceStep> {
+        self.context.push(&Token::Boundary);
+
+        let mut steps = Vec::new();
+        while steps.len() < max_tokens {
+            let step = self.step();
+            let ended = step.token == Token::Boundary;
+            steps.push(step);
+            if ended {
+                break;
+            }
+        }
+        self.context.push(&Token::Boundary);
+
+        steps
+    }
+
+    /// Generate the tokens of one whole sentence (or word), of up to max_tokens
+    pub(crate) fn generate_sentence(&mut self, max_tokens: usize) -> Vec<String> {
+        self.generate_traced(max_tokens)
+            .into_iter()
+            .filter_map(|step| match step.token {
+                Token::Token(value) => Some(value),
+                Token::Boundary => None,
+            })
+            .collect()
+    }
+
+    /// Continue the start of a sentence with up to max_tokens more tokens, until it ends
+    pub(crate) fn continue_sentence(&mut self, tokens: &mut Vec<String>, max_tokens: usize) {
+        self.context.push(&Token::Boundary);
+        for value in tokens.iter() {
+            self.context.push(&Token::from(value.as_str()));
+        }
+
+        for _ in 0..max_tokens {
+            match self.step().token {
+                Token::Token(value) => tokens.push(value),
+                Token::Boundary => break,
+            }
+        }
+        self.context.push(&Token::Boundary);
+    }
+
+    /// Walk backward from a token to the start of a sentence, returning the tokens leading up to
+    /// it, or None if no walk of up to max_tokens (with the token) reaches the start
+    pub(crate) fn walk_backward(&mut self, token: &Token, max_tokens: usize) -> Option<Vec<String>> {
+        walk_backward(&self.model.transitions, token, max_tokens, &mut self.rng)
+    }
+
+    /// Pick the next token and shift it into the context, recording how it was picked
+    fn step(&mut self) -> TraceStep {
+        step(&self.model.transitions, &mut self.context, self.temperature, self.backoff, &mut self.rng)
+    }
+}
+
+impl Iterator for GeneratorHandle {
+    type Item = String;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let Self { model, context, rng, temperature, backoff, sentence_start } = self;
+        let initial = match model.tokenizer_config {
+            TokenizerConfigs::Words => context.last_token() == Token::Boundary,
+            _ => *sentence_start,
+        };
+        let word = next_word(&model.tokenizer_config, &model.normalizer, initial, || {
+            step(&model.transitions, context, *temperature, *backoff, rng).token
+        });
+        // The other tokenizers end every word at a boundary, so only one ending in a sentence
+        // ending (or an empty word, ending a line) ends the sentence
+        *sentence_start = word.as_deref().is_none_or(ends_sentence);
+
+        word
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+    use std::thread;
+    use super::*;
+    use crate::generator::MarkovGenerator;
+    use crate::BoundaryConfigs;
+
+    fn model() -> Arc<MarkovModel> {
+        let mut generator = MarkovGenerator::with_tokenizer(
+            BoundaryConfigs::LineEndings, TokenizerConfigs::Words, 2
+        );
+        generator.train(Cursor::new("the cat sat\nthe dog ran\na bird flew\nthe cat sat")).unwrap();
+        Arc::new(generator.into_model())
+    }
+
+    #[test]
+    fn test_model_is_send_and_sync() {
+        fn assert_send_sync<T: Send + Sync>() {}
+        assert_send_sync::<MarkovModel>();
+
+        fn assert_send<T: Send>() {}
+        assert_send::<GeneratorHandle>();
+    }
+
+    #[test]
+    fn test_model_handles_across_threads() {
+        let model = model();
+
+        let threads: Vec<_> = (0..8)
+            .map(|i| {
+                let mut handle = model.seeded_generator(i);
+                thread::spawn(move || (0..10).map(|_| handle.generate_from("", 10)).collect::<Vec<_>>())
+            })
+            .collect();
+        let generated: Vec<Vec<String>> = threads.into_iter().map(|thread| thread.join().unwrap()).collect();
+
+        for sentences in &generated {
+            for sentence in sentences {
+                assert_eq!(model.score(sentence).unknown, 0, "Unexpected sentence {:?}", sentence);
+            }
+        }
+        // Seeded handles repeat themselves, whichever thread they run on
+        let mut handle = model.seeded_generator(3);
+        let repeated: Vec<String> = (0..10).map(|_| handle.generate_from("", 10)).collect();
+        assert_eq!(repeated, generated[3]);
+    }
+
+    #[test]
+    fn test_model_handle_state_is_its_own() {
+        let model = model();
+        let mut first = model.generator();
+        let mut second = model.generator();
+
+        first.set_temperature(0.01);
+        assert_eq!(second.temperature(), 1.0);
+
+        // Near zero, the most common continuation is always picked
+        assert_eq!(first.generate_from("the", 10), "the cat sat");
+        assert_eq!(second.generate_from("a", 10), "a bird flew");
+        assert_eq!(first.by_ref().take(10).count(), 3);
+        assert_eq!(model.stats(1).top_start_tokens, vec![("the".to_string(), 3.0)]);
+    }
+
+    #[test]
+    fn test_model_successors() {
+        let model = model();
+
+        let (state, successors) = model.successors("the").unwrap();
+        assert_eq!(state, Token::from("the"));
+        assert_eq!(successors, vec![(Token::from("cat"), 2.0 / 3.0), (Token::from("dog"), 1.0 / 3.0)]);
+
+        // "dog the" was never seen, so it backs off to "the"
+        assert_eq!(model.successors("dog the").unwrap().0, Token::from("the"));
+        let (state, successors) = model.successors("a bird").unwrap();
+        assert_eq!(state, Token::from("a bird"));
+        assert_eq!(successors, vec![(Token::from("flew"), 1.0)]);
+
+        assert_eq!(model.successors("").unwrap().0, Token::Boundary);
+        assert_eq!(model.successors("unknown"), None);
+    }
+}
@@ -0,0 +1,288 @@
+//! normalize module
+//!
+//! Contains Unicode normalisation and case folding of training text, and the restoring of
+//! casing in what is generated from it
+
+use std::borrow::Cow;
+use std::collections::HashMap;
+
+use unicode_normalization::{is_nfc_quick, is_nfkc_quick, IsNormalized, UnicodeNormalization};
+
+use crate::token::Token;
+use crate::tokenize::ends_sentence;
+use crate::{NormalizationConfigs, TokenizerConfigs};
+
+
+/// Normalise text to a Unicode normalisation form, borrowing it if it already is in it
+pub fn normalize_text<'a>(text: &'a str, normalization: &NormalizationConfigs) -> Cow<'a, str> {
+    match normalization {
+        NormalizationConfigs::None => Cow::Borrowed(text),
+        NormalizationConfigs::Nfc => match is_nfc_quick(text.chars()) {
+            IsNormalized::Yes => Cow::Borrowed(text),
+            _ => Cow::Owned(text.nfc().collect()),
+        },
+        NormalizationConfigs::Nfkc => match is_nfkc_quick(text.chars()) {
+            IsNormalized::Yes => Cow::Borrowed(text),
+            _ => Cow::Owned(text.nfkc().collect()),
+        },
+    }
+}
+
+/// How often a token was seen with one casing, in the middle of a sentence and at the start of
+/// one, where it may only be capitalised for being first
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord)]
+struct FormCount {
+    within: usize,
+    initial: usize,
+}
+
+/// Normalises and case folds tokens for training, and restores the casing of generated tokens
+///
+/// With case folding, "The" and "the" train as one token. The surface forms each folded token
+/// was trained from are counted, so generated tokens can be given back their most common one,
+/// preferring the forms seen in the middle of sentences so that a proper noun keeps its capital
+/// and a common word doesn't keep the one it had at the start of sentences. The first token of
+/// each generated sentence is capitalised.
+#[derive(Debug, Clone, Default)]
+pub struct Normalizer {
+    normalization: NormalizationConfigs,
+    fold_case: bool,
+    forms: HashMap<String, HashMap<String, FormCount>>,
+}
+
+impl Normalizer {
+    /// Construct a normalizer to a normalisation form, that also folds case if fold_case is set
+    pub fn new(normalization: NormalizationConfigs, fold_case: bool) -> Self {
+        Self {
+            normalization,
+            fold_case,
+            forms: HashMap::new(),
+        }
+    }
+
+    /// Set the normalisation form text is normalised to
+    pub fn set_normalization(&mut self, normalization: NormalizationConfigs) {
+        self.normalization = normalization;
+    }
+
+    /// Fold the case of tokens, counting the surface forms they were folded from
+    pub fn enable_case_folding(&mut self) {
+        self.fold_case = true;
+    }
+
+    /// The normalisation form text is normalised to
+    pub fn normalization(&self) -> &NormalizationConfigs {
+        &self.normalization
+    }
+
+    /// Whether tokens are case folded
+    pub fn folds_case(&self) -> bool {
+        self.fold_case
+    }
+
+    /// Normalise and case fold a token, the way training tokens are, such as one given to
+    /// generate from
+    pub fn fold(&self, token: &str) -> String {
+        let token = normalize_text(token, &self.normalization);
+        match self.fold_case {
+            true => token.to_lowercase(),
+            false => token.into_owned(),
+        }
+    }
+
+    /// Case fold a line of training tokens, counting the surface form of each one
+    ///
+    /// sentence_start says whether the first word starts a sentence, and is left saying whether
+    /// the word after the last one will, so it can be carried from line to line. With the word
+    /// tokenizer each Token::Boundary ends a sentence, but the others end every word in one, so
+    /// only a word ending in a sentence ending (or an empty word, ending a line) ends a sentence.
+    pub fn fold_training(
+        &mut self, tokens: Vec<Token>, tokenizer_config: &TokenizerConfigs, sentence_start: &mut bool
+    ) -> Vec<Token> {
+        if !self.fold_case {
+            return tokens;
+        }
+
+        let words = matches!(tokenizer_config, TokenizerConfigs::Words);
+        // The word so far, which only the other tokenizers split into pieces
+        let mut word = String::new();
+        tokens.into_iter()
+            .map(|token| match token {
+                Token::Token(surface) => {
+                    let folded = surface.to_lowercase();
+                    let initial = *sentence_start && word.is_empty();
+                    match words {
+                        true => *sentence_start = false,
+                        false => word.push_str(&surface),
+                    }
+                    let count = self.forms.entry(folded.clone()).or_default().entry(surface).or_default();
+                    match initial {
+                        true => count.initial += 1,
+                        false => count.within += 1,
+                    }
+                    Token::Token(folded)
+                },
+                Token::Boundary => {
+                    *sentence_start = words || word.is_empty() || ends_sentence(&word);
+                    word.clear();
+                    Token::Boundary
+                },
+            })
+            .collect()
+    }
+
+    /// Case fold a line of tokens without counting their surface forms, such as ones being
+    /// untrained
+    pub fn fold_tokens(&self, tokens: Vec<Token>) -> Vec<Token> {
+        if !self.fold_case {
+            return tokens;
+        }
+
+        tokens.into_iter()
+            .map(|token| match token {
+                Token::Token(value) => Token::Token(value.to_lowercase()),
+                Token::Boundary => Token::Boundary,
+            })
+            .collect()
+    }
+
+    /// The surface forms counted for each case folded token, with how often each was seen in the
+    /// middle of a sentence and at the start of one
+    pub(crate) fn forms(&self) -> impl Iterator<Item = (&str, &str, usize, usize)> {
+        self.forms.iter().flat_map(|(folded, forms)| {
+            forms.iter().map(move |(form, count)| (folded.as_str(), form.as_str(), count.within, count.initial))
+        })
+    }
+
+    /// Count a surface form of a case folded token, such as one loaded with a saved model
+    pub(crate) fn add_form(&mut self, folded: &str, form: &str, within: usize, initial: usize) {
+        let count = self.forms.entry(folded.to_string()).or_default().entry(form.to_string()).or_default();
+        count.within += within;
+        count.initial += initial;
+    }
+
+    /// Add the surface forms counted by another normalizer to this one's, such as one of a model
+    /// being merged into this one's
+    pub fn merge(&mut self, other: &Normalizer) {
+        for (folded, form, within, initial) in other.forms() {
+            self.add_form(folded, form, within, initial);
+        }
+    }
+
+    /// The casing of a generated token, capitalised if it starts a sentence
+    ///
+    /// Tokens aren't changed unless case is being folded.
+    pub fn restore(&self, token: &str, initial: bool) -> String {
+        if !self.fold_case {
+            return token.to_string();
+        }
+
+        let surface = self.forms.get(token)
+            .and_then(|forms| forms.iter().max_by(|(a_form, a), (b_form, b)| {
+                // Uppercase letters sort first, so ties go to the lowercase form
+                a.cmp(b).then_with(|| a_form.cmp(b_form))
+            }))
+            .map_or(token, |(form, _)| form.as_str());
+
+        match initial {
+            true => capitalize(surface),
+            false => surface.to_string(),
+        }
+    }
+
+    /// The casing of the tokens of a generated sentence
+    pub fn restore_sentence(&self, tokens: &[String]) -> Vec<String> {
+        tokens.iter()
+            .enumerate()
+            .map(|(i, token)| self.restore(token, i == 0))
+            .collect()
+    }
+}
+
+/// Uppercase the first character of a token
+fn capitalize(token: &str) -> String {
+    let mut chars = token.chars();
+    match chars.next() {
+        Some(first) => first.to_uppercase().chain(chars).collect(),
+        None => String::new(),
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tokens(values: &[&str]) -> Vec<Token> {
+        values.iter()
+            .map(|value| match *value {
+                "" => Token::Boundary,
+                value => Token::from(value),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_normalize_text() {
+        // "é" as "e" and a combining accent, and the "ﬁ" ligature
+        let text = "cafe\u{301} \u{FB01}ne";
+
+        assert_eq!(normalize_text(text, &NormalizationConfigs::None), text);
+        assert_eq!(normalize_text(text, &NormalizationConfigs::Nfc), "caf\u{E9} \u{FB01}ne");
+        assert_eq!(normalize_text(text, &NormalizationConfigs::Nfkc), "caf\u{E9} fine");
+        assert!(matches!(normalize_text("plain", &NormalizationConfigs::Nfkc), Cow::Borrowed(_)));
+    }
+
+    #[test]
+    fn test_fold_and_restore_casing() {
+        let mut normalizer = Normalizer::new(NormalizationConfigs::Nfc, true);
+        let mut sentence_start = true;
+        let folded = normalizer.fold_training(
+            tokens(&["The", "whale", "saw", "Ishmael", "", "The", "end", "", "Ishmael", "saw", "the", "Whale"]),
+            &TokenizerConfigs::Words,
+            &mut sentence_start,
+        );
+
+        assert_eq!(folded, tokens(&["the", "whale", "saw", "ishmael", "", "the", "end", "", "ishmael", "saw", "the", "whale"]));
+        assert!(!sentence_start);
+
+        // "the" was only capitalised for starting sentences, but "Ishmael" always is
+        assert_eq!(normalizer.restore("the", false), "the");
+        assert_eq!(normalizer.restore("ishmael", false), "Ishmael");
+        // Ties go to the lowercase form
+        assert_eq!(normalizer.restore("whale", false), "whale");
+        assert_eq!(normalizer.restore("unseen", false), "unseen");
+        assert_eq!(
+            normalizer.restore_sentence(&["the".to_string(), "end".to_string()]),
+            vec!["The", "end"],
+        );
+        assert_eq!(normalizer.fold("The\u{FB01}"), "the\u{FB01}");
+    }
+
+    #[test]
+    fn test_fold_characters_counts_sentence_starts() {
+        let mut normalizer = Normalizer::new(NormalizationConfigs::None, true);
+        let mut sentence_start = true;
+        // "The end. Met them", a character at a time
+        let line = tokens(&["T", "h", "e", "", "e", "n", "d", ".", "", "M", "e", "t", "", "t", "h", "e", "m", ""]);
+        normalizer.fold_training(line, &TokenizerConfigs::Characters, &mut sentence_start);
+
+        // Word boundaries don't start sentences, so only "T" and "M" were initial, not the "e" of
+        // "end" or the "t" of "them"
+        assert!(!sentence_start);
+        assert_eq!(normalizer.forms["t"]["T"], FormCount { within: 0, initial: 1 });
+        assert_eq!(normalizer.forms["t"]["t"], FormCount { within: 2, initial: 0 });
+        assert_eq!(normalizer.forms["m"]["M"], FormCount { within: 0, initial: 1 });
+        assert_eq!(normalizer.forms["e"]["e"], FormCount { within: 4, initial: 0 });
+    }
+
+    #[test]
+    fn test_no_folding_changes_nothing() {
+        let mut normalizer = Normalizer::default();
+        let line = tokens(&["The", "Whale"]);
+
+        assert_eq!(normalizer.fold_training(line.clone(), &TokenizerConfigs::Words, &mut true), line);
+        assert_eq!(normalizer.restore("the", true), "the");
+        assert_eq!(normalizer.fold("The"), "The");
+    }
+}
@@ -0,0 +1,108 @@
+//! originality module
+//!
+//! Contains an index of the n-grams in the training text, for catching generated text that
+//! copies it word for word
+
+use std::collections::{HashSet, VecDeque};
+
+use crate::token::Token;
+
+
+/// Every run of n consecutive tokens within a sentence of the training text
+#[derive(Debug, Clone)]
+pub struct NgramIndex {
+    n: usize,
+    ngrams: HashSet<Vec<String>>,
+    /// The last tokens of the sentence currently being added
+    tail: VecDeque<String>,
+}
+
+impl NgramIndex {
+    /// Construct an index that catches copies of more than max_copy consecutive tokens
+    pub fn new(max_copy: usize) -> NgramIndex {
+        NgramIndex {
+            n: max_copy + 1,
+            ngrams: HashSet::new(),
+            tail: VecDeque::new(),
+        }
+    }
+
+    /// Add the n-grams of training tokens, without crossing sentence boundaries
+    ///
+    /// A sentence left open at the end of the tokens carries on into the next call, so lines
+    /// wrapped in the middle of a sentence are indexed as one sentence
+    pub fn add(&mut self, tokens: &[Token]) {
+        for token in tokens {
+            match token {
+                Token::Token(value) => {
+                    if self.tail.len() == self.n {
+                        self.tail.pop_front();
+                    }
+                    self.tail.push_back(value.clone());
+                    if self.tail.len() == self.n {
+                        self.ngrams.insert(self.tail.iter().cloned().collect());
+                    }
+                },
+                Token::Boundary => self.tail.clear(),
+            }
+        }
+    }
+
+    /// Whether the tokens copy more than max_copy consecutive tokens from the training text
+    pub fn is_copy(&self, tokens: &[String]) -> bool {
+        tokens.windows(self.n).any(|ngram| self.ngrams.contains(ngram))
+    }
+
+    /// The fraction of the tokens that are not part of a run copied from the training text,
+    /// from 0 (copied word for word) to 1 (no runs longer than max_copy)
+    pub fn originality(&self, tokens: &[String]) -> f64 {
+        if tokens.is_empty() {
+            return 1.0;
+        }
+
+        let mut copied = vec![false; tokens.len()];
+        for (i, ngram) in tokens.windows(self.n).enumerate() {
+            if self.ngrams.contains(ngram) {
+                copied[i..i + self.n].fill(true);
+            }
+        }
+
+        let copied_count = copied.iter().filter(|copied| **copied).count();
+        1.0 - copied_count as f64 / tokens.len() as f64
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn strings(tokens: &[&str]) -> Vec<String> {
+        tokens.iter().map(|token| token.to_string()).collect()
+    }
+
+    #[test]
+    fn test_ngram_index() {
+        let mut index = NgramIndex::new(2);
+        index.add(&[
+            Token::Boundary,
+            Token::from("Call"),
+            Token::from("me"),
+            Token::from("Ishmael"),
+            Token::Boundary,
+            Token::from("Some"),
+        ]);
+        index.add(&[Token::from("years"), Token::from("ago"), Token::Boundary]);
+
+        assert!(index.is_copy(&strings(&["Call", "me", "Ishmael", "Some"])));
+        assert!(!index.is_copy(&strings(&["Call", "me", "Some", "years"])));
+        // Doesn't count runs across sentence boundaries
+        assert!(!index.is_copy(&strings(&["me", "Ishmael", "Some", "years"])));
+        // Does count runs across separately added lines
+        assert!(index.is_copy(&strings(&["Some", "years", "ago"])));
+
+        assert_eq!(index.originality(&strings(&["Call", "me", "Ishmael"])), 0.0);
+        assert_eq!(index.originality(&strings(&["Call", "me", "Ishmael", "Some"])), 0.25);
+        assert_eq!(index.originality(&strings(&["Call", "me"])), 1.0);
+    }
+}
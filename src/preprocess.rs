@@ -0,0 +1,389 @@
+//! preprocess module
+//!
+//! Contains preprocessors that strip boilerplate, like Project Gutenberg licenses and chapter
+//! headings, out of decoded lines before they are tokenized
+
+use crate::error::Error;
+use crate::StripConfigs;
+
+
+/// Longest line taken as an entry of a table of contents, rather than as the text after it
+const MAX_CONTENTS_ENTRY: usize = 80;
+
+/// Longest line taken as a chapter heading
+const MAX_HEADING: usize = 80;
+
+/// Words starting a chapter heading, before its number
+const HEADING_WORDS: [&str; 6] = ["chapter", "book", "part", "volume", "section", "canto"];
+
+/// Roman numerals, with the subtractive pairs, from the largest value down
+const ROMAN_NUMERALS: [(&str, usize); 13] = [
+    ("M", 1000), ("CM", 900), ("D", 500), ("CD", 400), ("C", 100), ("XC", 90),
+    ("L", 50), ("XL", 40), ("X", 10), ("IX", 9), ("V", 5), ("IV", 4), ("I", 1),
+];
+
+/// Where a Project Gutenberg ebook is up to
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Gutenberg {
+    /// Before the first line that isn't blank, which says whether this is an ebook
+    Unknown,
+    /// In the header of an ebook, up to its START marker
+    Header,
+    /// In the text itself, or in a document that isn't an ebook
+    Text,
+    /// In the license after the END marker
+    Footer,
+}
+
+/// Where a table of contents is up to
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Contents {
+    /// Not in one
+    Outside,
+    /// Past its heading, with this many blank lines in a row since its last entry (if it has had
+    /// any entries yet)
+    Inside { entries: bool, blank: usize },
+}
+
+/// Strips boilerplate out of the lines of one document, as it reads them
+///
+/// Stripped lines are read as empty lines, so the lines after them keep their line numbers.
+/// Each preprocessor can be turned on separately:
+/// - `Gutenberg` strips the header of a Project Gutenberg ebook up to its `*** START OF` marker,
+///   and the license from its `*** END OF` marker on, in documents that start by mentioning
+///   Project Gutenberg;
+/// - `Contents` strips a table of contents, from a `Contents` heading to the first two blank
+///   lines in a row after its entries (or the first line too long to be an entry);
+/// - `Chapters` strips chapter headings, like `CHAPTER 12. Biographical.` or `Book IV`, on a
+///   line of their own followed by a blank line (or the end of the document);
+/// - `PageNumbers` strips lines that are only a page number, like `[Page 12]` or `- 12 -`, and
+///   inline page markers like `[Pg 12]`.
+///
+/// # Examples
+/// ```rust
+/// use rusty_markov::{Preprocessor, StripConfigs};
+///
+/// let lines = ["CHAPTER 1. Loomings.", "", "Call me Ishmael. [Pg 2]", "Some years ago"];
+/// let mut preprocessor = Preprocessor::new(&[StripConfigs::Chapters, StripConfigs::PageNumbers]);
+/// let stripped: Vec<String> = lines.iter()
+///     .enumerate()
+///     .map(|(i, line)| preprocessor.process(line, lines.get(i + 1).copied()))
+///     .collect();
+///
+/// assert_eq!(stripped, ["", "", "Call me Ishmael.", "Some years ago"]);
+/// ```
+#[derive(Debug, Clone)]
+pub struct Preprocessor {
+    strips: Vec<StripConfigs>,
+    gutenberg: Gutenberg,
+    contents: Contents,
+}
+
+impl Preprocessor {
+    /// Construct a preprocessor for the start of a document, running the strips given
+    pub fn new(strips: &[StripConfigs]) -> Self {
+        Self {
+            strips: strips.to_vec(),
+            gutenberg: Gutenberg::Unknown,
+            contents: Contents::Outside,
+        }
+    }
+
+    /// Strip the lines of a document, passing errors through
+    pub fn process_lines<I: Iterator<Item = Result<String, Error>>>(
+        mut self, lines: I
+    ) -> impl Iterator<Item = Result<String, Error>> {
+        let mut lines = lines.peekable();
+        std::iter::from_fn(move || {
+            let line = lines.next()?;
+            let next = match lines.peek() {
+                Some(Ok(next)) => Some(next.as_str()),
+                _ => None,
+            };
+            Some(line.map(|line| self.process(&line, next)))
+        })
+    }
+
+    /// Strip the next line of the document, returning what is left of it
+    ///
+    /// next is the line after it, if there is one, which says whether a line that looks like a
+    /// chapter heading stands on its own or starts a paragraph.
+    pub fn process(&mut self, line: &str, next: Option<&str>) -> String {
+        // Blocks extracted from markup, each ending in a line break, stand on their own
+        if line.contains('\n') {
+            return line.split_inclusive('\n')
+                .map(|block| match self.process(block.trim_end_matches('\n'), None) {
+                    text if text.is_empty() => text,
+                    text => text + "\n",
+                })
+                .collect();
+        }
+
+        if self.strips.contains(&StripConfigs::Gutenberg) && self.strip_gutenberg(line) {
+            return String::new();
+        }
+        if self.strips.contains(&StripConfigs::Contents) && self.strip_contents(line) {
+            return String::new();
+        }
+        let standalone = next.is_none_or(|next| next.trim().is_empty());
+        if self.strips.contains(&StripConfigs::Chapters) && standalone && is_heading(line) {
+            return String::new();
+        }
+        if self.strips.contains(&StripConfigs::PageNumbers) {
+            if is_page_number(line) {
+                return String::new();
+            }
+            return strip_page_markers(line);
+        }
+
+        line.to_string()
+    }
+
+    /// Whether the line is part of the Project Gutenberg header or license
+    fn strip_gutenberg(&mut self, line: &str) -> bool {
+        let lowercase = line.trim().to_lowercase();
+        match self.gutenberg {
+            Gutenberg::Unknown if lowercase.is_empty() => true,
+            Gutenberg::Unknown if lowercase.contains("project gutenberg") => {
+                self.gutenberg = match is_marker(&lowercase, "start") {
+                    true => Gutenberg::Text,
+                    false => Gutenberg::Header,
+                };
+                true
+            },
+            Gutenberg::Unknown => {
+                self.gutenberg = Gutenberg::Text;
+                false
+            },
+            Gutenberg::Header => {
+                if is_marker(&lowercase, "start") {
+                    self.gutenberg = Gutenberg::Text;
+                }
+                true
+            },
+            Gutenberg::Text => {
+                let ended = is_marker(&lowercase, "end")
+                    || lowercase.starts_with("end of the project gutenberg")
+                    || lowercase.starts_with("end of project gutenberg");
+                if ended {
+                    self.gutenberg = Gutenberg::Footer;
+                }
+                ended
+            },
+            Gutenberg::Footer => true,
+        }
+    }
+
+    /// Whether the line is part of a table of contents
+    fn strip_contents(&mut self, line: &str) -> bool {
+        let trimmed = line.trim();
+        match self.contents {
+            Contents::Outside => {
+                let heading = trimmed.trim_end_matches(['.', ':']).to_lowercase();
+                if heading == "contents" || heading == "table of contents" {
+                    self.contents = Contents::Inside { entries: false, blank: 0 };
+                    return true;
+                }
+                false
+            },
+            Contents::Inside { entries, blank } => {
+                if trimmed.is_empty() {
+                    if entries && blank + 1 >= 2 {
+                        self.contents = Contents::Outside;
+                    } else {
+                        self.contents = Contents::Inside { entries, blank: blank + 1 };
+                    }
+                    return true;
+                }
+                if trimmed.chars().count() > MAX_CONTENTS_ENTRY {
+                    self.contents = Contents::Outside;
+                    return false;
+                }
+                self.contents = Contents::Inside { entries: true, blank: 0 };
+                true
+            },
+        }
+    }
+}
+
+/// Whether a lowercased line is a Project Gutenberg `*** START OF` or `*** END OF` marker
+fn is_marker(lowercase: &str, which: &str) -> bool {
+    lowercase.starts_with("***")
+        && lowercase.trim_start_matches(['*', ' ']).starts_with(&format!("{} of", which))
+        && lowercase.contains("project gutenberg")
+}
+
+/// Whether a line is a chapter heading, like `CHAPTER 12. Biographical.` or a roman numeral on
+/// its own
+fn is_heading(line: &str) -> bool {
+    let trimmed = line.trim();
+    if trimmed.is_empty() || trimmed.chars().count() > MAX_HEADING {
+        return false;
+    }
+
+    let mut words = trimmed.split_whitespace();
+    let first = words.next().unwrap_or_default();
+    if is_roman_numeral(first.trim_end_matches('.')) && words.next().is_none() {
+        return true;
+    }
+
+    let Some(number) = words.next() else {
+        return false;
+    };
+    let number = number.trim_end_matches(['.', ':']);
+    HEADING_WORDS.contains(&first.to_lowercase().as_str())
+        && (number.chars().all(|c| c.is_ascii_digit()) || is_roman_numeral(number))
+}
+
+/// Whether a word is an uppercase roman numeral, written the standard way, so not a word made of
+/// numeral letters like `DID` or `CIVIL`, nor `IIII`
+fn is_roman_numeral(word: &str) -> bool {
+    let Some(values) = word.chars().map(roman_digit).collect::<Option<Vec<usize>>>() else {
+        return false;
+    };
+
+    // A numeral before a larger one is subtracted from it, which leaves less subtracted than added
+    let (mut added, mut subtracted) = (0, 0);
+    for (i, &digit) in values.iter().enumerate() {
+        match values.get(i + 1) {
+            Some(&next) if next > digit => subtracted += digit,
+            _ => added += digit,
+        }
+    }
+
+    // A standard numeral is the one written back from its value
+    let mut value = added - subtracted;
+    let mut written = String::new();
+    for (numeral, numeral_value) in ROMAN_NUMERALS {
+        while value >= numeral_value {
+            written.push_str(numeral);
+            value -= numeral_value;
+        }
+    }
+    !word.is_empty() && written == word
+}
+
+/// The value of a single roman numeral letter
+fn roman_digit(letter: char) -> Option<usize> {
+    ROMAN_NUMERALS.iter()
+        .find(|(numeral, _)| numeral.len() == 1 && numeral.starts_with(letter))
+        .map(|&(_, value)| value)
+}
+
+/// Whether a line is only a page number, like `12`, `[Page 12]`, `p. 12` or `- 12 -`
+fn is_page_number(line: &str) -> bool {
+    let trimmed = line.trim().trim_matches(['[', ']', '{', '}', '-', ' ']);
+    let lowercase = trimmed.to_lowercase();
+    let number = ["page", "pg", "p."].iter()
+        .find_map(|prefix| lowercase.strip_prefix(prefix))
+        .unwrap_or(&lowercase)
+        .trim();
+
+    !number.is_empty() && number.chars().all(|c| c.is_ascii_digit())
+}
+
+/// Remove page markers, like `[Pg 12]` or `[Page 12]`, from inside a line
+fn strip_page_markers(line: &str) -> String {
+    let mut stripped = String::with_capacity(line.len());
+    let mut rest = line;
+
+    while let Some(start) = rest.find('[') {
+        let marker = rest[start..].find(']').map(|end| &rest[start..start + end + 1]);
+        match marker {
+            Some(marker) if is_page_number(marker) => {
+                stripped.push_str(rest[..start].trim_end_matches([' ', '\t']));
+                rest = &rest[start + marker.len()..];
+                // Keep the words either side of the marker apart
+                if !stripped.is_empty() && rest.starts_with(|c: char| !c.is_whitespace()) {
+                    stripped.push(' ');
+                }
+            },
+            _ => {
+                stripped.push_str(&rest[..=start]);
+                rest = &rest[start + 1..];
+            },
+        }
+    }
+    stripped.push_str(rest);
+
+    stripped
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn process(strips: &[StripConfigs], text: &str) -> Vec<String> {
+        let preprocessor = Preprocessor::new(strips);
+        preprocessor.process_lines(text.lines().map(|line| Ok(line.to_string())))
+            .map(Result::unwrap)
+            .collect()
+    }
+
+    #[test]
+    fn test_strip_gutenberg() {
+        let text = "\u{FEFF}The Project Gutenberg eBook of Moby Dick\n\nTitle: Moby Dick\n\
+            *** START OF THE PROJECT GUTENBERG EBOOK MOBY DICK ***\nCall me Ishmael.\n\
+            *** END OF THE PROJECT GUTENBERG EBOOK MOBY DICK ***\nSection 1. General Terms";
+
+        assert_eq!(
+            process(&[StripConfigs::Gutenberg], text),
+            vec!["", "", "", "", "Call me Ishmael.", "", ""],
+        );
+
+        // Documents that don't start by mentioning Project Gutenberg are left alone
+        let text = "Call me Ishmael.\nI read it on Project Gutenberg.\n*** START OF THE PROJECT GUTENBERG EBOOK ***";
+        assert_eq!(process(&[StripConfigs::Gutenberg], text), text.lines().collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_strip_contents() {
+        let text = "MOBY-DICK\n\nCONTENTS\n\nETYMOLOGY.\n\nCHAPTER 1. Loomings.\n\nEpilogue\n\n\n\
+            Original Transcriber’s Notes:\nCONTENTS.\nChapter I\nChapter II\nThis line of the text is much too long to be an entry in a table of contents, so it ends it";
+
+        assert_eq!(
+            process(&[StripConfigs::Contents], text),
+            vec![
+                "MOBY-DICK", "", "", "", "", "", "", "", "", "", "", "Original Transcriber’s Notes:",
+                "", "", "",
+                "This line of the text is much too long to be an entry in a table of contents, so it ends it",
+            ],
+        );
+    }
+
+    #[test]
+    fn test_strip_chapters_and_page_numbers() {
+        let text = "CHAPTER 12. Biographical.\n\nBook IV\n\nXIV.\n\nChapter one of my life\n\nI.\n\
+            I went.\nBOOK I. (Folio), CHAPTER I. (Sperm Whale).—This whale, among the\nEnglish\n\
+            42\n[Page 42]\n- 43 -\np. 44\nthe [Pg 45]whale [sic] swam [Pg 46]";
+
+        assert_eq!(
+            process(&[StripConfigs::Chapters, StripConfigs::PageNumbers], text),
+            vec![
+                "", "", "", "", "", "", "Chapter one of my life", "",
+                // Headings have to stand on their own, not start a paragraph
+                "I.", "I went.", "BOOK I. (Folio), CHAPTER I. (Sperm Whale).—This whale, among the",
+                "English", "", "", "", "", "the whale [sic] swam",
+            ],
+        );
+        assert_eq!(process(&[], text), text.lines().collect::<Vec<_>>());
+
+        let mut preprocessor = Preprocessor::new(&[StripConfigs::Chapters]);
+        assert_eq!(preprocessor.process("Chapter 1\nCall me Ishmael\n", Some("Some years")), "Call me Ishmael\n");
+    }
+
+    #[test]
+    fn test_roman_numerals() {
+        for numeral in ["I", "IV", "IX", "XIV", "XL", "XCIX", "CDXLIV", "MCMLXXXIV", "MMXXVI"] {
+            assert!(is_roman_numeral(numeral), "{}", numeral);
+        }
+        // Words made of numeral letters, and numerals not written the standard way
+        for word in ["", "DID", "MILD", "CIVIL", "IIII", "VV", "IC", "XM", "IXI", "Iv"] {
+            assert!(!is_roman_numeral(word), "{}", word);
+        }
+
+        // So a shouted word on its own isn't taken for a heading
+        assert_eq!(process(&[StripConfigs::Chapters], "XIV.\n\nDID.\n\nCHAPTER DID"), vec!["", "", "DID.", "", "CHAPTER DID"]);
+    }
+}
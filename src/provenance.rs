@@ -0,0 +1,165 @@
+//! provenance module
+//!
+//! Contains the record of where in the training documents each transition was counted
+
+use std::collections::HashMap;
+use std::fmt;
+use std::hash::Hash;
+
+use crate::token::Token;
+
+
+/// A line of a training document
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Location {
+    pub document: String,
+    /// Line number, counting from 1
+    pub line: usize,
+}
+
+impl fmt::Display for Location {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}", self.document, self.line)
+    }
+}
+
+/// A compact Location, with the document as an index into the document names
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Source {
+    document: usize,
+    line: usize,
+}
+
+/// Source locations for each transition
+#[derive(Debug, Clone)]
+pub struct Provenance<T = String> {
+    documents: Vec<String>,
+    /// Where transitions being counted right now come from
+    current: Option<Source>,
+    sources: HashMap<Token<T>, HashMap<Token<T>, Vec<Source>>>,
+}
+
+impl<T: Hash + Eq> PartialEq for Provenance<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.documents == other.documents && self.sources == other.sources
+    }
+}
+
+#[cfg(feature = "memory-profiling")]
+use memuse::DynamicUsage;
+
+#[cfg(feature = "memory-profiling")]
+impl<T: DynamicUsage> DynamicUsage for Provenance<T> {
+    fn dynamic_usage(&self) -> usize {
+        let sources: usize = self.sources.iter()
+            .map(|(last_token, next_tokens)| {
+                last_token.dynamic_usage() + next_tokens.iter()
+                    .map(|(next_token, sources)| {
+                        next_token.dynamic_usage() + sources.capacity() * std::mem::size_of::<Source>()
+                    })
+                    .sum::<usize>()
+            })
+            .sum();
+
+        self.documents.dynamic_usage() + sources
+    }
+
+    fn dynamic_usage_bounds(&self) -> (usize, Option<usize>) {
+        let usage = self.dynamic_usage();
+        (usage, Some(usage))
+    }
+}
+
+impl<T: Hash + Eq + Clone> Default for Provenance<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T: Hash + Eq + Clone> Provenance<T> {
+    pub fn new() -> Provenance<T> {
+        Provenance {
+            documents: Vec::new(),
+            current: None,
+            sources: HashMap::new(),
+        }
+    }
+
+    /// Set the location that transitions recorded from now on come from
+    pub fn set_source(&mut self, document: &str, line: usize) {
+        // Lines mostly come from the same document as the line before them
+        let current = self.current.map(|source| source.document)
+            .filter(|&index| self.documents[index] == document);
+        let document = match current.or_else(|| self.documents.iter().position(|known| known == document)) {
+            Some(index) => index,
+            None => {
+                self.documents.push(document.to_string());
+                self.documents.len() - 1
+            }
+        };
+
+        self.current = Some(Source { document, line });
+    }
+
+    /// Record the current location as a source of the last_token to next_token transition
+    pub fn record(&mut self, last_token: &Token<T>, next_token: &Token<T>) {
+        let source = match self.current {
+            Some(source) => source,
+            None => return,
+        };
+
+        let sources = self.sources
+            .entry(last_token.clone())
+            .or_default()
+            .entry(next_token.clone())
+            .or_default();
+
+        // The same transition can turn up more than once in a line
+        if sources.last() != Some(&source) {
+            sources.push(source);
+        }
+    }
+
+    /// Every location the last_token to next_token transition was counted in
+    pub fn locations(&self, last_token: &Token<T>, next_token: &Token<T>) -> Vec<Location> {
+        let sources = match self.sources.get(last_token).and_then(|next| next.get(next_token)) {
+            Some(sources) => sources,
+            None => return Vec::new(),
+        };
+
+        sources.iter()
+            .map(|source| Location {
+                document: self.documents[source.document].clone(),
+                line: source.line,
+            })
+            .collect()
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_provenance_locations() {
+        let mut provenance = Provenance::new();
+        let (call, me) = (Token::from("Call"), Token::from("me"));
+
+        // Nothing is recorded until there is a source
+        provenance.record(&call, &me);
+        assert_eq!(provenance.locations(&call, &me), vec![]);
+
+        provenance.set_source("moby-dick.txt", 3);
+        provenance.record(&call, &me);
+        provenance.record(&call, &me);
+        provenance.set_source("other.txt", 1);
+        provenance.record(&call, &me);
+
+        let locations: Vec<String> = provenance.locations(&call, &me).iter()
+            .map(Location::to_string)
+            .collect();
+        assert_eq!(locations, vec!["moby-dick.txt:3", "other.txt:1"]);
+        assert_eq!(provenance.locations(&me, &call), vec![]);
+    }
+}
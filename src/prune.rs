@@ -0,0 +1,199 @@
+//! prune module
+//!
+//! Contains pruning of rare transitions and vocabulary, for shrinking large models
+
+use std::collections::{HashMap, HashSet};
+
+use crate::token::Token;
+use crate::transitions::Transitions;
+
+
+/// The token rare vocabulary is replaced by
+pub const UNKNOWN: &str = "<unk>";
+
+/// What to prune from trained transitions. The default prunes nothing.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Pruning {
+    /// Transitions counted fewer times than this are dropped
+    pub min_count: f64,
+    /// States observed fewer times than this, across all their transitions, are dropped
+    pub min_state_count: f64,
+    /// Only this many of the most common tokens following each state are kept
+    pub top_n: Option<usize>,
+    /// Tokens seen fewer times than this are replaced by UNKNOWN, before anything else is pruned
+    pub min_token_count: f64,
+}
+
+impl Default for Pruning {
+    fn default() -> Self {
+        Pruning {
+            min_count: 1.0,
+            min_state_count: 1.0,
+            top_n: None,
+            min_token_count: 1.0,
+        }
+    }
+}
+
+impl Pruning {
+    /// Prune the transitions
+    ///
+    /// split breaks a state into the tokens it is made of and join puts them back together,
+    /// so rare tokens can be replaced within states of any order. For a chain of order 1, a
+    /// state is just the one token.
+    pub fn apply<S, J>(&self, transitions: &mut Transitions, split: S, join: J)
+    where
+        S: Fn(&str) -> Vec<String>,
+        J: Fn(&[String]) -> String,
+    {
+        if self.min_token_count > 1.0 {
+            let rare = rare_tokens(transitions, self.min_token_count);
+            if !rare.is_empty() {
+                transitions.map_tokens(|token| match token {
+                    Token::Token(value) => {
+                        let parts: Vec<String> = split(value).into_iter()
+                            .map(|part| match rare.contains(&part) {
+                                true => UNKNOWN.to_string(),
+                                false => part,
+                            })
+                            .collect();
+                        Token::Token(join(&parts))
+                    },
+                    Token::Boundary => Token::Boundary,
+                });
+            }
+        }
+
+        if self.min_count > 1.0 {
+            transitions.retain(|_, _, count| count >= self.min_count);
+        }
+
+        if self.min_state_count > 1.0 {
+            let observations = state_observations(transitions);
+            transitions.retain(|state, _, _| observations[state] >= self.min_state_count);
+        }
+
+        if let Some(top_n) = self.top_n {
+            let kept = top_tokens(transitions, top_n);
+            transitions.retain(|state, token, _| kept.contains(&(state.clone(), token.clone())));
+        }
+    }
+}
+
+/// Tokens picked fewer than min_count times in all
+///
+/// Every token in the training text is picked exactly once, so this is how often it was seen
+fn rare_tokens(transitions: &Transitions, min_count: f64) -> HashSet<String> {
+    let mut counts: HashMap<&str, f64> = HashMap::new();
+    for state in transitions.last_tokens() {
+        for (token, count) in transitions.next_tokens(state).into_iter().flatten() {
+            if let Token::Token(value) = token {
+                *counts.entry(value).or_default() += count;
+            }
+        }
+    }
+
+    counts.into_iter()
+        .filter(|(_, count)| *count < min_count)
+        .map(|(token, _)| token.to_string())
+        .collect()
+}
+
+/// Total count of all the transitions from each state
+fn state_observations(transitions: &Transitions) -> HashMap<Token, f64> {
+    transitions.last_tokens()
+        .map(|state| {
+            let total = transitions.next_tokens(state).into_iter()
+                .flatten()
+                .map(|(_, count)| count)
+                .sum();
+            (state.clone(), total)
+        })
+        .collect()
+}
+
+/// The top_n most common transitions from each state, breaking ties by the token so pruning is
+/// repeatable
+fn top_tokens(transitions: &Transitions, top_n: usize) -> HashSet<(Token, Token)> {
+    let mut kept = HashSet::new();
+    for state in transitions.last_tokens() {
+        let mut next_tokens: Vec<(&Token, &f64)> = transitions.next_tokens(state).into_iter()
+            .flatten()
+            .collect();
+        next_tokens.sort_by(|(a_token, a_count), (b_token, b_count)| {
+            b_count.total_cmp(a_count).then_with(|| a_token.cmp(b_token))
+        });
+        for (token, _) in next_tokens.into_iter().take(top_n) {
+            kept.insert((state.clone(), token.clone()));
+        }
+    }
+
+    kept
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::train::train_with_tokens;
+
+    fn transitions() -> Transitions {
+        let mut transitions = Transitions::new();
+        let lines = [vec!["the", "cat"], vec!["the", "cat"], vec!["the", "bat"], vec!["teh", "cat"]];
+        for tokens in lines {
+            let mut line = vec![Token::Boundary];
+            line.extend(tokens.into_iter().map(Token::from));
+            line.push(Token::Boundary);
+            train_with_tokens(line, &mut transitions);
+        }
+        transitions
+    }
+
+    fn prune(pruning: Pruning) -> Transitions {
+        let mut transitions = transitions();
+        pruning.apply(&mut transitions, |state| vec![state.to_string()], |parts| parts.concat());
+        transitions
+    }
+
+    #[test]
+    fn test_prune_nothing() {
+        assert_eq!(prune(Pruning::default()), transitions());
+    }
+
+    #[test]
+    fn test_prune_counts() {
+        let pruned = prune(Pruning { min_count: 2.0, ..Pruning::default() });
+        assert_eq!(
+            pruned.next_tokens(&Token::from("the")),
+            Some(&HashMap::from([(Token::from("cat"), 2.0)])),
+        );
+        assert_eq!(pruned.next_tokens(&Token::from("teh")), None);
+
+        let pruned = prune(Pruning { min_state_count: 2.0, ..Pruning::default() });
+        assert_eq!(pruned.len(), 5);
+        assert_eq!(pruned.next_tokens(&Token::from("bat")), None);
+
+        let pruned = prune(Pruning { top_n: Some(1), ..Pruning::default() });
+        assert_eq!(pruned.start_tokens(), Some(&HashMap::from([(Token::from("the"), 3.0)])));
+        assert_eq!(pruned.len(), 5);
+    }
+
+    #[test]
+    fn test_prune_rare_tokens() {
+        let pruned = prune(Pruning { min_token_count: 2.0, ..Pruning::default() });
+        let unknown = Token::from(UNKNOWN);
+
+        assert_eq!(
+            pruned.start_tokens(),
+            Some(&HashMap::from([(Token::from("the"), 3.0), (unknown.clone(), 1.0)])),
+        );
+        assert_eq!(
+            pruned.next_tokens(&Token::from("the")),
+            Some(&HashMap::from([(Token::from("cat"), 2.0), (unknown.clone(), 1.0)])),
+        );
+        assert_eq!(
+            pruned.next_tokens(&unknown),
+            Some(&HashMap::from([(Token::Boundary, 1.0), (Token::from("cat"), 1.0)])),
+        );
+    }
+}
@@ -0,0 +1,190 @@
+//! repl module
+//!
+//! Contains an interactive loop for exploring a trained model
+
+use std::io::{self, BufRead, Write};
+use std::sync::Arc;
+
+use crate::model::{GeneratorHandle, MarkovModel};
+use crate::token::Token;
+
+
+/// Number of successors shown by `:next`
+const SUCCESSORS: usize = 20;
+
+const HELP: &str = "\
+Commands:
+  (empty line)       generate another sentence
+  <text>             continue a sentence from the text
+  :generate [n]      generate n sentences (1 by default)
+  :next [text]       show the tokens that could follow the text, with their probabilities
+  :score <text>      score how likely a sentence is
+  :temperature <t>   set the temperature tokens are picked with (1 by default)
+  :seed <n>          restart the random number generator from a seed
+  :help              show this help
+  :quit              leave (as does the end of the input)
+";
+
+/// Reads commands for exploring a trained model, and writes what they show
+///
+/// Everything is generated with one generator, so a seed applies to everything after it.
+pub struct Repl {
+    generator: GeneratorHandle,
+    max_tokens: usize,
+}
+
+impl Repl {
+    /// Construct a REPL over a trained model, generating up to max_tokens for each sentence
+    pub fn new(model: Arc<MarkovModel>, max_tokens: usize) -> Repl {
+        Repl {
+            generator: model.generator(),
+            max_tokens,
+        }
+    }
+
+    /// Run commands from input until it ends or a `:quit`, prompting for each one on output
+    pub fn run<R: BufRead, W: Write>(&mut self, input: R, mut output: W) -> io::Result<()> {
+        writeln!(output, "Type :help for commands")?;
+        write!(output, "> ")?;
+        output.flush()?;
+
+        for line in input.lines() {
+            let line = line?;
+            let (command, argument) = match line.trim().split_once(char::is_whitespace) {
+                Some((command, argument)) => (command, argument.trim()),
+                None => (line.trim(), ""),
+            };
+
+            match command {
+                "" => self.generate(1, &mut output)?,
+                ":generate" | ":g" => match parse_or(argument, 1) {
+                    Some(sentences) => self.generate(sentences, &mut output)?,
+                    None => writeln!(output, "Not a number of sentences: {}", argument)?,
+                },
+                ":next" | ":n" => self.successors(argument, &mut output)?,
+                ":score" | ":s" => {
+                    writeln!(output, "{}", self.generator.model().score(argument))?;
+                },
+                ":temperature" | ":t" => match argument.parse::<f64>() {
+                    Ok(temperature) if temperature > 0.0 && temperature.is_finite() => {
+                        self.generator.set_temperature(temperature);
+                    },
+                    _ => writeln!(output, "The temperature has to be a positive number")?,
+                },
+                ":seed" => match argument.parse::<u64>() {
+                    Ok(seed) => self.generator.seed(seed),
+                    Err(_) => writeln!(output, "Not a seed: {}", argument)?,
+                },
+                ":help" | ":h" => write!(output, "{}", HELP)?,
+                ":quit" | ":q" => return Ok(()),
+                command if command.starts_with(':') => {
+                    writeln!(output, "Unknown command {}, type :help for commands", command)?;
+                },
+                _ => {
+                    let sentence = self.generator.generate_from(line.trim(), self.max_tokens);
+                    writeln!(output, "{}", sentence)?;
+                },
+            }
+
+            write!(output, "> ")?;
+            output.flush()?;
+        }
+
+        writeln!(output)
+    }
+
+    fn generate<W: Write>(&mut self, sentences: usize, output: &mut W) -> io::Result<()> {
+        for _ in 0..sentences {
+            writeln!(output, "{}", self.generator.generate_from("", self.max_tokens))?;
+        }
+
+        Ok(())
+    }
+
+    fn successors<W: Write>(&self, text: &str, output: &mut W) -> io::Result<()> {
+        let Some((state, successors)) = self.generator.model().successors(text) else {
+            return writeln!(output, "Nothing is known to follow that");
+        };
+
+        if let Token::Token(state) = &state
+            && !text.ends_with(state.as_str())
+        {
+            writeln!(output, "(backed off to \"{}\")", state)?;
+        }
+        for (token, probability) in successors.iter().take(SUCCESSORS) {
+            let token = match token {
+                Token::Token(value) => value.as_str(),
+                Token::Boundary => "<end>",
+            };
+            writeln!(output, "  {:>6.2}%  {}", probability * 100.0, token)?;
+        }
+        if successors.len() > SUCCESSORS {
+            writeln!(output, "  ({} more)", successors.len() - SUCCESSORS)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Parse a number, or take the default if there is none
+fn parse_or(value: &str, default: usize) -> Option<usize> {
+    match value {
+        "" => Some(default),
+        value => value.parse().ok(),
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+    use super::*;
+    use crate::generator::MarkovGenerator;
+    use crate::{BoundaryConfigs, TokenizerConfigs};
+
+    fn repl() -> Repl {
+        let mut generator = MarkovGenerator::with_tokenizer(
+            BoundaryConfigs::LineEndings, TokenizerConfigs::Words, 2
+        );
+        generator.train(Cursor::new("the cat sat\nthe cat sat\nthe dog ran")).unwrap();
+        Repl::new(Arc::new(generator.into_model()), 20)
+    }
+
+    fn run(commands: &str) -> Vec<String> {
+        let mut output = Vec::new();
+        repl().run(Cursor::new(commands), &mut output).unwrap();
+        String::from_utf8(output).unwrap()
+            .split("> ")
+            .skip(1)
+            .map(String::from)
+            .collect()
+    }
+
+    #[test]
+    fn test_repl_commands() {
+        let outputs = run(concat!(
+            "the dog\n:next the\n:next the cat sat\n:score the cat sat\n",
+            ":temperature 0\n:t 0.01\n:g 2\n:quit\n:next the\n",
+        ));
+
+        assert_eq!(outputs[0], "the dog ran\n");
+        assert_eq!(outputs[1], "   66.67%  cat\n   33.33%  dog\n");
+        assert_eq!(outputs[2], "  100.00%  <end>\n");
+        assert!(outputs[3].starts_with("log probability -0.405 over 4 tokens"), "{}", outputs[3]);
+        assert_eq!(outputs[4], "The temperature has to be a positive number\n");
+        assert_eq!(outputs[5], "");
+        assert_eq!(outputs[6], "the cat sat\nthe cat sat\n");
+        assert_eq!(outputs.len(), 8, "Should stop at :quit");
+    }
+
+    #[test]
+    fn test_repl_seed_and_errors() {
+        let outputs = run(":seed 5\n\n:seed 5\n\n:seed five\n:next nothing\n:bogus\n");
+
+        assert_eq!(outputs[1], outputs[3], "The same seed should generate the same sentence");
+        assert_eq!(outputs[4], "Not a seed: five\n");
+        assert_eq!(outputs[5], "Nothing is known to follow that\n");
+        assert_eq!(outputs[6], "Unknown command :bogus, type :help for commands\n");
+        assert_eq!(outputs[7], "\n", "Should end the prompt line at the end of the input");
+    }
+}
@@ -0,0 +1,272 @@
+//! saved module
+//!
+//! Contains the file format models are saved in, which keeps the options a model was trained with
+//! next to its transitions, so it can't be loaded with options that would misread them
+
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+
+use clap::ValueEnum;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use serde::ser::SerializeStruct;
+
+use crate::normalize::Normalizer;
+use crate::transitions::Transitions;
+use crate::{BoundaryConfigs, NormalizationConfigs, TokenizerConfigs};
+
+
+/// The options a model was trained with, which it has to be generated from and trained on with
+///
+/// A model file keeps them next to the transitions, in the shape sketched on
+/// [`crate::train_with_tokens`], and with case folding, next to the surface forms each folded
+/// token was seen with, counted in the middle of sentences and at their start:
+/// ```json
+/// {
+///   "order": 1,
+///   "tokenizer": "words",
+///   "boundaries": "line-endings",
+///   "normalization": "none",
+///   "fold_case": true,
+///   "forms": {
+///     "the": { "The": [0, 1], "the": [1, 0] }
+///   },
+///   "transitions": {
+///     "the": { "cat": 2, "": 1 },
+///     "": { "the": 1 }
+///   }
+/// }
+/// ```
+/// Files saved before the normalisation and case folding were kept load as having neither.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ModelSettings {
+    pub order: usize,
+    pub tokenizer: TokenizerConfigs,
+    pub boundaries: BoundaryConfigs,
+    pub normalization: NormalizationConfigs,
+    pub fold_case: bool,
+}
+
+impl ModelSettings {
+    /// Export a model trained with these settings as pretty printed JSON, with the surface forms
+    /// the normalizer counted
+    pub fn to_json(&self, transitions: &Transitions, normalizer: &Normalizer) -> String {
+        // Settings and maps of strings to numbers can always be serialized
+        serde_json::to_string_pretty(&SavedModel { settings: self, transitions, normalizer }).unwrap()
+    }
+
+    /// Import a model exported by to_json, with the settings it was trained with and a normalizer
+    /// for them, with the surface forms it counted
+    pub fn from_json(json: &str) -> Result<(ModelSettings, Transitions, Normalizer), serde_json::Error> {
+        serde_json::from_str::<LoadedModel>(json).map(LoadedModel::into_parts)
+    }
+
+    /// Export a model trained with these settings as YAML, with the surface forms the normalizer
+    /// counted
+    pub fn to_yaml(&self, transitions: &Transitions, normalizer: &Normalizer) -> String {
+        serde_norway::to_string(&SavedModel { settings: self, transitions, normalizer }).unwrap()
+    }
+
+    /// Import a model exported by to_yaml, with the settings it was trained with and a normalizer
+    /// for them, with the surface forms it counted
+    pub fn from_yaml(yaml: &str) -> Result<(ModelSettings, Transitions, Normalizer), serde_norway::Error> {
+        serde_norway::from_str::<LoadedModel>(yaml).map(LoadedModel::into_parts)
+    }
+
+    /// Checks that a model trained with these settings can be used with the expected ones,
+    /// describing the difference if it can't
+    pub fn check(&self, expected: &ModelSettings) -> Result<(), String> {
+        match self == expected {
+            true => Ok(()),
+            false => Err(format!("it was trained with {}, not {}", self, expected)),
+        }
+    }
+}
+
+impl fmt::Display for ModelSettings {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f, "--order {} --tokenizer {} --boundaries {}",
+            self.order, value_name(&self.tokenizer), value_name(&self.boundaries)
+        )?;
+        if self.normalization != NormalizationConfigs::None {
+            write!(f, " --normalization {}", value_name(&self.normalization))?;
+        }
+        if self.fold_case {
+            write!(f, " --fold-case")?;
+        }
+
+        Ok(())
+    }
+}
+
+/// A model being saved, borrowing its transitions so they don't have to be copied
+struct SavedModel<'a> {
+    settings: &'a ModelSettings,
+    transitions: &'a Transitions,
+    normalizer: &'a Normalizer,
+}
+
+impl Serialize for SavedModel<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut saved = serializer.serialize_struct("SavedModel", 7)?;
+        saved.serialize_field("order", &self.settings.order)?;
+        saved.serialize_field("tokenizer", &value_name(&self.settings.tokenizer))?;
+        saved.serialize_field("boundaries", &value_name(&self.settings.boundaries))?;
+        saved.serialize_field("normalization", &value_name(&self.settings.normalization))?;
+        saved.serialize_field("fold_case", &self.settings.fold_case)?;
+        if self.settings.fold_case {
+            // Sorted, so saving the same model again writes the same file
+            let mut forms: BTreeMap<&str, BTreeMap<&str, [usize; 2]>> = BTreeMap::new();
+            for (folded, form, within, initial) in self.normalizer.forms() {
+                forms.entry(folded).or_default().insert(form, [within, initial]);
+            }
+            saved.serialize_field("forms", &forms)?;
+        }
+        saved.serialize_field("transitions", self.transitions)?;
+        saved.end()
+    }
+}
+
+/// A model being loaded, which has to say what it was trained with
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct LoadedModel {
+    order: usize,
+    #[serde(deserialize_with = "value_enum")]
+    tokenizer: TokenizerConfigs,
+    #[serde(deserialize_with = "value_enum")]
+    boundaries: BoundaryConfigs,
+    #[serde(default, deserialize_with = "value_enum")]
+    normalization: NormalizationConfigs,
+    #[serde(default)]
+    fold_case: bool,
+    #[serde(default)]
+    forms: HashMap<String, HashMap<String, [usize; 2]>>,
+    transitions: Transitions,
+}
+
+impl LoadedModel {
+    fn into_parts(self) -> (ModelSettings, Transitions, Normalizer) {
+        let mut normalizer = Normalizer::new(self.normalization.clone(), self.fold_case);
+        for (folded, forms) in &self.forms {
+            for (form, [within, initial]) in forms {
+                normalizer.add_form(folded, form, *within, *initial);
+            }
+        }
+        let settings = ModelSettings {
+            order: self.order,
+            tokenizer: self.tokenizer,
+            boundaries: self.boundaries,
+            normalization: self.normalization,
+            fold_case: self.fold_case,
+        };
+        (settings, self.transitions, normalizer)
+    }
+}
+
+/// The name an option value is given with on the command line
+fn value_name<E: ValueEnum>(value: &E) -> String {
+    // None of the configs skip a value
+    value.to_possible_value().unwrap().get_name().to_string()
+}
+
+/// Reads an option value by the name it is given with on the command line
+fn value_enum<'de, D: Deserializer<'de>, E: ValueEnum>(deserializer: D) -> Result<E, D::Error> {
+    let name = String::deserialize(deserializer)?;
+    E::from_str(&name, false).map_err(serde::de::Error::custom)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+    use crate::generator::MarkovGenerator;
+    use crate::token::Token;
+
+    fn settings() -> ModelSettings {
+        ModelSettings {
+            order: 1,
+            tokenizer: TokenizerConfigs::Words,
+            boundaries: BoundaryConfigs::LineEndings,
+            normalization: NormalizationConfigs::None,
+            fold_case: false,
+        }
+    }
+
+    #[test]
+    fn test_saved_model_json_and_yaml() {
+        let mut transitions = Transitions::new();
+        transitions.count_transition(&Token::Boundary, &Token::from("the"));
+        transitions.count_transition(&Token::from("the"), &Token::Boundary);
+        let normalizer = Normalizer::default();
+
+        let json = settings().to_json(&transitions, &normalizer);
+        assert_eq!(
+            json,
+            "{\n  \"order\": 1,\n  \"tokenizer\": \"words\",\n  \"boundaries\": \"line-endings\",\n  \
+            \"normalization\": \"none\",\n  \"fold_case\": false,\n  \
+            \"transitions\": {\n    \"the\": {\n      \"\": 1\n    },\n    \"\": {\n      \"the\": 1\n    }\n  }\n}"
+        );
+        let (loaded_settings, loaded, _) = ModelSettings::from_json(&json).unwrap();
+        assert_eq!(loaded_settings, settings());
+        assert_eq!(loaded, transitions);
+        let (loaded_settings, loaded, _) = ModelSettings::from_yaml(&settings().to_yaml(&transitions, &normalizer)).unwrap();
+        assert_eq!(loaded_settings, settings());
+        assert_eq!(loaded, transitions);
+
+        // A bare map of transitions doesn't say what it was trained with
+        assert!(ModelSettings::from_json(r#"{"the": {"cat": 1}}"#).is_err());
+        assert!(ModelSettings::from_json(
+            r#"{"order": 1, "tokenizer": "syllables", "boundaries": "line-endings", "transitions": {}}"#
+        ).is_err());
+
+        // Files from before normalisation and case folding were saved have neither
+        let (empty_settings, empty, _) = ModelSettings::from_json(
+            r#"{"order": 2, "tokenizer": "characters", "boundaries": "sentence-endings", "transitions": {}}"#
+        ).unwrap();
+        assert_eq!(empty_settings.tokenizer, TokenizerConfigs::Characters);
+        assert!(!empty_settings.fold_case);
+        assert_eq!(empty, HashMap::new());
+    }
+
+    #[test]
+    fn test_saved_model_keeps_casing() {
+        let mut generator = MarkovGenerator::new(BoundaryConfigs::SentenceEndings);
+        generator.enable_case_folding();
+        generator.train(Cursor::new("The Cat ran to Paris.")).unwrap();
+        let folded = ModelSettings { boundaries: BoundaryConfigs::SentenceEndings, fold_case: true, ..settings() };
+
+        let json = folded.to_json(generator.transitions(), generator.normalizer());
+        assert!(json.contains("\"forms\": {\n    \"cat\": {\n      \"Cat\": [\n        1,\n        0\n      ]"), "{}", json);
+        let (loaded_settings, transitions, normalizer) = ModelSettings::from_yaml(
+            &folded.to_yaml(generator.transitions(), generator.normalizer())
+        ).unwrap();
+        assert_eq!(loaded_settings, folded);
+
+        let mut loaded = MarkovGenerator::new(BoundaryConfigs::SentenceEndings);
+        loaded.set_transitions(transitions);
+        loaded.set_normalizer(normalizer);
+        assert_eq!(loaded.generate_from("the", 10), "The Cat ran to Paris");
+    }
+
+    #[test]
+    fn test_saved_model_check() {
+        assert!(settings().check(&settings()).is_ok());
+
+        let order_3 = ModelSettings { order: 3, ..settings() };
+        assert_eq!(
+            settings().check(&order_3).unwrap_err(),
+            "it was trained with --order 1 --tokenizer words --boundaries line-endings, \
+            not --order 3 --tokenizer words --boundaries line-endings"
+        );
+        let characters = ModelSettings { tokenizer: TokenizerConfigs::Characters, ..settings() };
+        assert!(settings().check(&characters).is_err());
+        let folded = ModelSettings { normalization: NormalizationConfigs::Nfc, fold_case: true, ..settings() };
+        assert_eq!(
+            folded.check(&settings()).unwrap_err(),
+            "it was trained with --order 1 --tokenizer words --boundaries line-endings --normalization nfc --fold-case, \
+            not --order 1 --tokenizer words --boundaries line-endings"
+        );
+    }
+}
@@ -0,0 +1,130 @@
+//! score module
+//!
+//! Contains how likely a sentence is under trained transitions, for comparing text to a model
+
+use std::fmt;
+
+use crate::context::Context;
+use crate::token::Token;
+use crate::transitions::Transitions;
+
+
+/// How likely a sentence is to be generated
+///
+/// Each token, and the Token::Boundary ending the sentence, is scored by the probability of the
+/// longest state before it that has ever been followed by it picking it, counted without any
+/// temperature. Tokens no state could have picked are counted as unknown, rather than making the
+/// whole sentence impossible.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SentenceScore {
+    /// Number of tokens scored, counting the boundary ending the sentence
+    pub tokens: usize,
+    /// Natural log of the probability of all the known tokens being picked
+    pub log_probability: f64,
+    /// Number of tokens that couldn't have been picked from any state before them
+    pub unknown: usize,
+}
+
+impl SentenceScore {
+    /// Score the tokens of a sentence, in a context at its start
+    pub fn from_tokens(transitions: &Transitions, tokens: &[String], mut context: Context) -> SentenceScore {
+        let mut score = SentenceScore {
+            tokens: 0,
+            log_probability: 0.0,
+            unknown: 0,
+        };
+
+        let tokens = tokens.iter()
+            .map(|value| Token::from(value.as_str()))
+            .chain([Token::Boundary]);
+        for token in tokens {
+            let probability = context.backoff_states().iter()
+                .filter_map(|state| transitions.next_tokens(state))
+                .find_map(|next_tokens| {
+                    let total: f64 = next_tokens.values().sum();
+                    next_tokens.get(&token).map(|count| count / total)
+                });
+            match probability {
+                Some(probability) => score.log_probability += probability.ln(),
+                None => score.unknown += 1,
+            }
+            score.tokens += 1;
+            context.push(&token);
+        }
+
+        score
+    }
+
+    /// The perplexity of the known tokens, the number of equally likely tokens the model was as
+    /// unsure as picking between on average, or None if none of them were known
+    pub fn perplexity(&self) -> Option<f64> {
+        let known = self.tokens - self.unknown;
+        match known {
+            0 => None,
+            known => Some((-self.log_probability / known as f64).exp()),
+        }
+    }
+
+    /// Render the score as a JSON object
+    pub fn to_json(&self) -> String {
+        let perplexity = match self.perplexity() {
+            Some(perplexity) => perplexity.to_string(),
+            None => "null".to_string(),
+        };
+        format!(
+            "{{\"tokens\":{},\"log_probability\":{},\"perplexity\":{},\"unknown\":{}}}",
+            self.tokens, self.log_probability, perplexity, self.unknown,
+        )
+    }
+}
+
+impl fmt::Display for SentenceScore {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "log probability {:.3} over {} tokens", self.log_probability, self.tokens)?;
+        if let Some(perplexity) = self.perplexity() {
+            write!(f, ", perplexity {:.2}", perplexity)?;
+        }
+        if self.unknown > 0 {
+            write!(f, ", {} unknown", self.unknown)?;
+        }
+
+        Ok(())
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::TokenizerConfigs;
+    use crate::train::train_with_tokens;
+
+    #[test]
+    fn test_score_sentence() {
+        let mut transitions = Transitions::new();
+        for line in [["the", "cat"], ["the", "bat"]] {
+            let mut tokens = vec![Token::Boundary];
+            tokens.extend(line.into_iter().map(Token::from));
+            tokens.push(Token::Boundary);
+            train_with_tokens(tokens, &mut transitions);
+        }
+        let context = Context::new(1, &TokenizerConfigs::Words);
+        let tokens = |sentence: &str| sentence.split(' ').map(String::from).collect::<Vec<_>>();
+
+        let score = SentenceScore::from_tokens(&transitions, &tokens("the cat"), context.clone());
+        assert_eq!(score.tokens, 3);
+        assert_eq!(score.unknown, 0);
+        assert!((score.log_probability - 0.5f64.ln()).abs() < 1e-9);
+        assert!((score.perplexity().unwrap() - 2f64.powf(1.0 / 3.0)).abs() < 1e-9);
+        assert_eq!(
+            score.to_json(),
+            format!("{{\"tokens\":3,\"log_probability\":{},\"perplexity\":{},\"unknown\":0}}",
+                score.log_probability, score.perplexity().unwrap()),
+        );
+
+        let score = SentenceScore::from_tokens(&transitions, &tokens("dog"), context);
+        assert_eq!(score.unknown, 2);
+        assert_eq!(score.perplexity(), None);
+        assert_eq!(score.to_string(), "log probability 0.000 over 2 tokens, 2 unknown");
+    }
+}
@@ -0,0 +1,436 @@
+//! serve module
+//!
+//! Contains a small HTTP server for generating from a model over the network, with JSON requests
+//! and responses
+
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use serde_json::Value;
+
+use crate::model::MarkovModel;
+use crate::json::json_string;
+
+
+/// What is wrong with a request, as the HTTP status and the error message to respond with
+type Rejection = (u16, String);
+
+/// An HTTP request, read as far as the endpoints need
+struct Request {
+    method: String,
+    path: String,
+    body: String,
+}
+
+/// Largest request body read, in bytes
+const MAX_BODY: usize = 1 << 20;
+
+/// Largest request line and headers read, in bytes all together
+const MAX_HEAD: usize = 16 << 10;
+
+/// Most sentences generated for one request
+const MAX_SENTENCES: usize = 100;
+
+/// How long a connection has to send the whole of its request, and to take its response
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Most connections handled at once, each on its own thread
+const MAX_CONNECTIONS: usize = 64;
+
+/// Serves a trained model over HTTP, handling each connection on its own thread with its own
+/// generator, up to MAX_CONNECTIONS at once
+///
+/// The endpoints are:
+/// - `POST /generate`, with an optional JSON object of `seed`, `prompt`, `max_tokens` (up to
+///   the server's own), `sentences` and `temperature`, responding with `{"sentences": [...]}`;
+/// - `POST /score`, with `{"text": "..."}`, responding with the score of the text;
+/// - `GET /stats`, responding with the statistics of the model.
+///
+/// Errors respond with `{"error": "..."}`.
+pub struct Server {
+    model: Arc<MarkovModel>,
+    stats: String,
+    max_tokens: usize,
+}
+
+impl Server {
+    /// Construct a server for a trained model, generating up to max_tokens for each sentence
+    /// unless a request asks for fewer
+    pub fn new(model: Arc<MarkovModel>, max_tokens: usize) -> Server {
+        // The model doesn't change while serving, so neither do its stats
+        let stats = model.stats(10).to_json();
+        Server {
+            model,
+            stats,
+            max_tokens,
+        }
+    }
+
+    /// Accept connections for as long as the listener does
+    ///
+    /// Connections that can't be accepted, such as when the process is out of file descriptors,
+    /// are logged and skipped. Past MAX_CONNECTIONS, connections wait to be accepted until one
+    /// being handled is done.
+    pub fn run(self, listener: TcpListener) -> io::Result<()> {
+        let server = Arc::new(self);
+        let limit = Arc::new(ConnectionLimit::default());
+        for stream in listener.incoming() {
+            let stream = match stream {
+                Ok(stream) => stream,
+                Err(e) => {
+                    eprintln!("Error accepting connection: {}", e);
+                    continue;
+                }
+            };
+            let slot = limit.acquire();
+            let server = Arc::clone(&server);
+            thread::spawn(move || {
+                if let Err(e) = server.handle(stream) {
+                    eprintln!("Error handling request: {}", e);
+                }
+                drop(slot);
+            });
+        }
+
+        Ok(())
+    }
+
+    /// Read one request from a connection and write the response to it
+    fn handle(&self, stream: TcpStream) -> io::Result<()> {
+        stream.set_write_timeout(Some(REQUEST_TIMEOUT))?;
+        let deadline = Instant::now() + REQUEST_TIMEOUT;
+        let mut reader = BufReader::new(DeadlineReader { stream: &stream, deadline });
+        let request = match read_request(&mut reader) {
+            Ok(request) => request,
+            Err(e) if matches!(e.kind(), io::ErrorKind::TimedOut | io::ErrorKind::WouldBlock) => {
+                Err((408, "request timed out".to_string()))
+            },
+            Err(e) => return Err(e),
+        };
+        let (status, body) = match request {
+            Ok(request) => self.respond(&request.method, &request.path, &request.body),
+            Err((status, message)) => (status, error_json(&message)),
+        };
+
+        write_response(&stream, status, &body)
+    }
+
+    /// The status and JSON body responding to a request
+    pub fn respond(&self, method: &str, path: &str, body: &str) -> (u16, String) {
+        let result = match (method, path) {
+            ("POST", "/generate") => parse_body(body).and_then(|request| self.generate(&request)),
+            ("POST", "/score") => parse_body(body).and_then(|request| self.score(&request)),
+            ("GET", "/stats") => Ok(self.stats.clone()),
+            (_, "/generate" | "/score" | "/stats") => Err((405, "method not allowed".to_string())),
+            _ => Err((404, "not found".to_string())),
+        };
+
+        match result {
+            Ok(body) => (200, body),
+            Err((status, message)) => (status, error_json(&message)),
+        }
+    }
+
+    fn generate(&self, request: &Value) -> Result<String, Rejection> {
+        let seed = optional(request, "seed", Value::as_u64)?;
+        let prompt = optional(request, "prompt", Value::as_str)?.unwrap_or("");
+        let max_tokens = optional(request, "max_tokens", Value::as_u64)?
+            .map_or(self.max_tokens, |max_tokens| (max_tokens as usize).min(self.max_tokens));
+        let sentences = optional(request, "sentences", Value::as_u64)?.unwrap_or(1) as usize;
+        let temperature = optional(request, "temperature", Value::as_f64)?.unwrap_or(1.0);
+
+        if sentences > MAX_SENTENCES {
+            return Err((400, format!("at most {} sentences can be generated at once", MAX_SENTENCES)));
+        }
+        if temperature <= 0.0 || !temperature.is_finite() {
+            return Err((400, "temperature has to be a positive number".to_string()));
+        }
+
+        let mut generator = match seed {
+            Some(seed) => self.model.seeded_generator(seed),
+            None => self.model.generator(),
+        };
+        generator.set_temperature(temperature);
+        let generated: Vec<String> = (0..sentences)
+            .map(|_| json_string(&generator.generate_from(prompt, max_tokens)))
+            .collect();
+
+        Ok(format!("{{\"sentences\":[{}]}}", generated.join(",")))
+    }
+
+    fn score(&self, request: &Value) -> Result<String, Rejection> {
+        let text = optional(request, "text", Value::as_str)?
+            .ok_or_else(|| (400, "text is required".to_string()))?;
+
+        Ok(self.model.score(text).to_json())
+    }
+}
+
+/// Parse a request body as a JSON object, with an empty body as an empty object
+fn parse_body(body: &str) -> Result<Value, Rejection> {
+    if body.trim().is_empty() {
+        return Ok(Value::Object(Default::default()));
+    }
+
+    match serde_json::from_str(body) {
+        Ok(value @ Value::Object(_)) => Ok(value),
+        Ok(_) => Err((400, "the request body has to be a JSON object".to_string())),
+        Err(e) => Err((400, format!("invalid JSON: {}", e))),
+    }
+}
+
+/// A field of a request, if it is there, erroring if it isn't the type get expects
+fn optional<'a, T>(
+    request: &'a Value, name: &str, get: fn(&'a Value) -> Option<T>
+) -> Result<Option<T>, Rejection> {
+    match request.get(name) {
+        None | Some(Value::Null) => Ok(None),
+        Some(value) => get(value)
+            .map(Some)
+            .ok_or_else(|| (400, format!("{} has the wrong type", name))),
+    }
+}
+
+/// Counts the connections being handled, so no more than MAX_CONNECTIONS are at once
+#[derive(Default)]
+struct ConnectionLimit {
+    handling: Mutex<usize>,
+    freed: Condvar,
+}
+
+impl ConnectionLimit {
+    /// Take a slot for a connection, waiting for one to be freed if they are all taken
+    fn acquire(self: &Arc<Self>) -> ConnectionSlot {
+        // Nothing panics while holding the lock, so it can't be poisoned
+        let mut handling = self.handling.lock().unwrap();
+        while *handling >= MAX_CONNECTIONS {
+            handling = self.freed.wait(handling).unwrap();
+        }
+        *handling += 1;
+
+        ConnectionSlot(Arc::clone(self))
+    }
+}
+
+/// A connection's slot, freed when it is dropped, even by a thread that panicked
+struct ConnectionSlot(Arc<ConnectionLimit>);
+
+impl Drop for ConnectionSlot {
+    fn drop(&mut self) {
+        *self.0.handling.lock().unwrap() -= 1;
+        self.0.freed.notify_one();
+    }
+}
+
+/// Reads from a connection, timing out once its deadline has passed, however slowly the bytes
+/// before it trickled in
+struct DeadlineReader<'a> {
+    stream: &'a TcpStream,
+    deadline: Instant,
+}
+
+impl Read for DeadlineReader<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let left = self.deadline.saturating_duration_since(Instant::now());
+        if left.is_zero() {
+            return Err(io::Error::from(io::ErrorKind::TimedOut));
+        }
+        self.stream.set_read_timeout(Some(left))?;
+        self.stream.read(buf)
+    }
+}
+
+fn error_json(message: &str) -> String {
+    format!("{{\"error\":{}}}", json_string(message))
+}
+
+/// Read the method, path and body of an HTTP request, or the status and message of what is
+/// wrong with it
+fn read_request<R: BufRead>(reader: &mut R) -> io::Result<Result<Request, Rejection>> {
+    let mut line = String::new();
+    let mut head_left = MAX_HEAD;
+    if let Err(rejection) = read_head_line(reader, &mut line, &mut head_left)? {
+        return Ok(Err(rejection));
+    }
+    let mut parts = line.split_whitespace();
+    let (Some(method), Some(target)) = (parts.next(), parts.next()) else {
+        return Ok(Err((400, "malformed request line".to_string())));
+    };
+    // Query strings aren't used by any endpoint
+    let path = target.split('?').next().unwrap_or(target);
+    let (method, path) = (method.to_string(), path.to_string());
+
+    let mut content_length = 0;
+    loop {
+        if let Err(rejection) = read_head_line(reader, &mut line, &mut head_left)? {
+            return Ok(Err(rejection));
+        }
+        if line.is_empty() {
+            break;
+        }
+        let header = line.trim_end();
+        if header.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = header.split_once(':')
+            && name.trim().eq_ignore_ascii_case("content-length")
+        {
+            content_length = match value.trim().parse() {
+                Ok(length) => length,
+                Err(_) => return Ok(Err((400, "invalid Content-Length".to_string()))),
+            };
+        }
+    }
+
+    if content_length > MAX_BODY {
+        return Ok(Err((413, "request body too large".to_string())));
+    }
+    let mut body = vec![0; content_length];
+    reader.read_exact(&mut body)?;
+
+    Ok(match String::from_utf8(body) {
+        Ok(body) => Ok(Request { method, path, body }),
+        Err(_) => Err((400, "the request body isn't UTF-8".to_string())),
+    })
+}
+
+/// Read a line of the request line and headers into line, rejecting it if they run past head_left
+/// more bytes. An empty line is the end of the input.
+fn read_head_line<R: BufRead>(
+    reader: &mut R, line: &mut String, head_left: &mut usize
+) -> io::Result<Result<(), Rejection>> {
+    line.clear();
+    *head_left -= reader.take(*head_left as u64).read_line(line)?;
+    match *head_left == 0 && !line.ends_with('\n') {
+        true => Ok(Err((431, "request line or headers too large".to_string()))),
+        false => Ok(Ok(())),
+    }
+}
+
+fn write_response<W: Write>(mut writer: W, status: u16, body: &str) -> io::Result<()> {
+    let reason = match status {
+        200 => "OK",
+        400 => "Bad Request",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        408 => "Request Timeout",
+        413 => "Payload Too Large",
+        431 => "Request Header Fields Too Large",
+        _ => "Error",
+    };
+    write!(
+        writer,
+        "HTTP/1.1 {} {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status, reason, body.len(), body,
+    )?;
+    writer.flush()
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Cursor, Read};
+    use super::*;
+    use crate::generator::MarkovGenerator;
+    use crate::{BoundaryConfigs, TokenizerConfigs};
+
+    fn server() -> Server {
+        let mut generator = MarkovGenerator::with_tokenizer(
+            BoundaryConfigs::LineEndings, TokenizerConfigs::Words, 1
+        );
+        generator.train(Cursor::new("the cat sat\nthe dog ran\na bird flew")).unwrap();
+        Server::new(Arc::new(generator.into_model()), 20)
+    }
+
+    #[test]
+    fn test_serve_respond() {
+        let server = server();
+
+        let (status, body) = server.respond("POST", "/generate", r#"{"prompt": "a", "sentences": 2}"#);
+        assert_eq!(status, 200);
+        assert_eq!(body, r#"{"sentences":["a bird flew","a bird flew"]}"#);
+
+        let seeded = r#"{"seed": 3, "sentences": 5, "temperature": 2.5}"#;
+        assert_eq!(server.respond("POST", "/generate", seeded), server.respond("POST", "/generate", seeded));
+
+        let (status, body) = server.respond("POST", "/score", r#"{"text": "a bird flew"}"#);
+        assert_eq!(status, 200);
+        assert!(body.starts_with(r#"{"tokens":4,"#), "{}", body);
+
+        let (status, body) = server.respond("GET", "/stats", "");
+        assert_eq!(status, 200);
+        assert!(body.starts_with(r#"{"vocabulary":8,"#), "{}", body);
+
+        assert_eq!(server.respond("POST", "/score", "{}").0, 400);
+        assert_eq!(server.respond("POST", "/generate", r#"{"temperature": 0}"#).0, 400);
+        assert_eq!(server.respond("POST", "/generate", r#"{"seed": "three"}"#).0, 400);
+        assert_eq!(server.respond("POST", "/generate", "[").0, 400);
+        assert_eq!(server.respond("GET", "/generate", "").0, 405);
+        assert_eq!(server.respond("GET", "/", "").0, 404);
+    }
+
+    #[test]
+    fn test_serve_max_tokens() {
+        let mut generator = MarkovGenerator::new(BoundaryConfigs::LineEndings);
+        generator.train(Cursor::new("one two three four five six")).unwrap();
+        let server = Server::new(Arc::new(generator.into_model()), 3);
+
+        let fewer = server.respond("POST", "/generate", r#"{"max_tokens": 2}"#);
+        assert_eq!(fewer, (200, r#"{"sentences":["one two"]}"#.to_string()));
+        let more = server.respond("POST", "/generate", r#"{"max_tokens": 1000000}"#);
+        assert_eq!(more, (200, r#"{"sentences":["one two three"]}"#.to_string()), "Should be capped");
+    }
+
+    #[test]
+    fn test_serve_rejects_long_and_slow_requests() {
+        let long_header = format!("GET /stats HTTP/1.1\r\nX-Long: {}\r\n\r\n", "a".repeat(MAX_HEAD));
+        let rejection = read_request(&mut Cursor::new(long_header)).unwrap().err();
+        assert_eq!(rejection.map(|(status, _)| status), Some(431));
+        let many_headers = format!("GET /stats HTTP/1.1\r\n{}\r\n", "X-Short: a\r\n".repeat(MAX_HEAD / 10));
+        let rejection = read_request(&mut Cursor::new(many_headers)).unwrap().err();
+        assert_eq!(rejection.map(|(status, _)| status), Some(431));
+        let request = read_request(&mut Cursor::new("GET /stats HTTP/1.1\r\nHost: a\r\n\r\n")).unwrap().unwrap();
+        assert_eq!(request.path, "/stats");
+
+        // A connection that sends nothing times out at its deadline, not a read timeout later
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let _client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (stream, _) = listener.accept().unwrap();
+        let mut reader = DeadlineReader { stream: &stream, deadline: Instant::now() + Duration::from_millis(50) };
+        let error = reader.read(&mut [0; 16]).unwrap_err();
+        assert!(matches!(error.kind(), io::ErrorKind::TimedOut | io::ErrorKind::WouldBlock), "{}", error);
+        assert_eq!(reader.read(&mut [0; 16]).unwrap_err().kind(), io::ErrorKind::TimedOut);
+    }
+
+    #[test]
+    fn test_serve_localhost() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let server = server();
+        thread::spawn(move || server.run(listener));
+
+        let clients: Vec<_> = (0..8)
+            .map(|_| thread::spawn(move || {
+                let mut stream = TcpStream::connect(address).unwrap();
+                let body = r#"{"prompt": "the cat"}"#;
+                write!(
+                    stream,
+                    "POST /generate HTTP/1.1\r\nHost: localhost\r\nContent-Length: {}\r\n\r\n{}",
+                    body.len(), body,
+                ).unwrap();
+                let mut response = String::new();
+                stream.read_to_string(&mut response).unwrap();
+                response
+            }))
+            .collect();
+
+        for client in clients {
+            let response = client.join().unwrap();
+            assert!(response.starts_with("HTTP/1.1 200 OK\r\n"), "{}", response);
+            assert!(response.ends_with(r#"{"sentences":["the cat sat"]}"#), "{}", response);
+        }
+    }
+}
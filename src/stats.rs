@@ -0,0 +1,238 @@
+//! stats module
+//!
+//! Contains summary statistics of trained transitions, for inspecting a model
+
+use std::collections::HashSet;
+use std::fmt;
+
+use crate::json::{json_string, token_json};
+use crate::token::Token;
+use crate::transitions::Transitions;
+
+
+/// Summary statistics of trained transitions
+#[derive(Debug, Clone, PartialEq)]
+pub struct ModelStats {
+    /// Number of distinct tokens that can be generated
+    pub vocabulary: usize,
+    /// Number of states tokens are picked from
+    pub states: usize,
+    /// Total count of every transition
+    pub total_transitions: f64,
+    /// Number of distinct state to token transitions
+    pub distinct_transitions: usize,
+    /// Average number of distinct tokens following each state
+    pub branching_factor: f64,
+    /// Number of distinct transitions with counts in each power of two range, as
+    /// `(lowest count, highest count, transitions)`, with fractional counts rounded up
+    pub count_histogram: Vec<(u32, u32, usize)>,
+    /// The most common tokens starting a sentence, with their counts
+    pub top_start_tokens: Vec<(String, f64)>,
+    /// States reached by a transition that have no transitions of their own
+    pub dead_ends: Vec<String>,
+    /// The most common n-grams, a state followed by a token, with their counts
+    pub top_ngrams: Vec<(Vec<Token>, f64)>,
+}
+
+impl ModelStats {
+    /// Gather statistics from trained transitions, listing the top_n of the top lists
+    ///
+    /// next_state works out the state that follows a state and the token picked from it, which
+    /// is just the token for a chain of order 1
+    pub fn from_transitions<F: Fn(&Token, &Token) -> Token>(
+        transitions: &Transitions, top_n: usize, next_state: F
+    ) -> ModelStats {
+        let mut vocabulary: HashSet<&Token> = HashSet::new();
+        let mut total_transitions = 0.0;
+        let mut distinct_transitions = 0;
+        let mut count_histogram: Vec<(u32, u32, usize)> = Vec::new();
+        let mut ngrams: Vec<(Vec<Token>, f64)> = Vec::new();
+        let mut dead_ends: HashSet<Token> = HashSet::new();
+        let mut states = 0;
+
+        for state in transitions.last_tokens() {
+            states += 1;
+            // A state always has successors, or it wouldn't be there
+            let next_tokens = transitions.next_tokens(state).unwrap();
+
+            for (token, count) in next_tokens {
+                if let Token::Token(_) = token {
+                    vocabulary.insert(token);
+                    let following = next_state(state, token);
+                    if transitions.next_tokens(&following).is_none() {
+                        dead_ends.insert(following);
+                    }
+                }
+                total_transitions += count;
+                distinct_transitions += 1;
+                ngrams.push((vec![state.clone(), token.clone()], *count));
+
+                // Buckets are 1, 2-3, 4-7, ...
+                let whole_count = (count.ceil() as u32).max(1);
+                let bucket = (u32::BITS - 1 - whole_count.leading_zeros()) as usize;
+                if count_histogram.len() <= bucket {
+                    for i in count_histogram.len()..=bucket {
+                        let low = 1u32 << i;
+                        count_histogram.push((low, low.saturating_mul(2) - 1, 0));
+                    }
+                }
+                count_histogram[bucket].2 += 1;
+            }
+        }
+
+        let mut top_start_tokens: Vec<(String, f64)> = transitions.start_tokens()
+            .into_iter()
+            .flatten()
+            .filter_map(|(token, count)| match token {
+                Token::Token(value) => Some((value.clone(), *count)),
+                Token::Boundary => None,
+            })
+            .collect();
+        sort_by_count(&mut top_start_tokens);
+        top_start_tokens.truncate(top_n);
+
+        sort_by_count(&mut ngrams);
+        ngrams.truncate(top_n);
+
+        let mut dead_ends: Vec<String> = dead_ends.into_iter()
+            .filter_map(|state| match state {
+                Token::Token(value) => Some(value),
+                Token::Boundary => None,
+            })
+            .collect();
+        dead_ends.sort();
+
+        ModelStats {
+            vocabulary: vocabulary.len(),
+            states,
+            total_transitions,
+            distinct_transitions,
+            branching_factor: match states {
+                0 => 0.0,
+                states => distinct_transitions as f64 / states as f64,
+            },
+            count_histogram,
+            top_start_tokens,
+            dead_ends,
+            top_ngrams: ngrams,
+        }
+    }
+
+    /// Render the statistics as a JSON object
+    pub fn to_json(&self) -> String {
+        let histogram: Vec<String> = self.count_histogram.iter()
+            .map(|(low, high, transitions)| {
+                format!("{{\"min\":{},\"max\":{},\"transitions\":{}}}", low, high, transitions)
+            })
+            .collect();
+        let start_tokens: Vec<String> = self.top_start_tokens.iter()
+            .map(|(token, count)| format!("{{\"token\":{},\"count\":{}}}", json_string(token), count))
+            .collect();
+        let dead_ends: Vec<String> = self.dead_ends.iter().map(|state| json_string(state)).collect();
+        let ngrams: Vec<String> = self.top_ngrams.iter()
+            .map(|(ngram, count)| {
+                let tokens: Vec<String> = ngram.iter().map(token_json).collect();
+                format!("{{\"ngram\":[{}],\"count\":{}}}", tokens.join(","), count)
+            })
+            .collect();
+
+        format!(
+            "{{\"vocabulary\":{},\"states\":{},\"total_transitions\":{},\"distinct_transitions\":{},\
+            \"branching_factor\":{},\"count_histogram\":[{}],\"top_start_tokens\":[{}],\
+            \"dead_ends\":[{}],\"top_ngrams\":[{}]}}",
+            self.vocabulary,
+            self.states,
+            self.total_transitions,
+            self.distinct_transitions,
+            self.branching_factor,
+            histogram.join(","),
+            start_tokens.join(","),
+            dead_ends.join(","),
+            ngrams.join(","),
+        )
+    }
+}
+
+impl fmt::Display for ModelStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Vocabulary size:      {}", self.vocabulary)?;
+        writeln!(f, "States:               {}", self.states)?;
+        writeln!(f, "Total transitions:    {}", self.total_transitions)?;
+        writeln!(f, "Distinct transitions: {}", self.distinct_transitions)?;
+        writeln!(f, "Branching factor:     {:.2}", self.branching_factor)?;
+
+        writeln!(f, "\nTransition counts:")?;
+        for (low, high, transitions) in &self.count_histogram {
+            let range = match low == high {
+                true => low.to_string(),
+                false => format!("{}-{}", low, high),
+            };
+            writeln!(f, "  {:>13}: {}", range, transitions)?;
+        }
+
+        writeln!(f, "\nTop start tokens:")?;
+        for (token, count) in &self.top_start_tokens {
+            writeln!(f, "  {:>8}  {}", count, token)?;
+        }
+
+        writeln!(f, "\nDead ends ({}):", self.dead_ends.len())?;
+        for state in &self.dead_ends {
+            writeln!(f, "  {}", state)?;
+        }
+
+        writeln!(f, "\nTop n-grams:")?;
+        for (ngram, count) in &self.top_ngrams {
+            let tokens: Vec<&str> = ngram.iter()
+                .map(|token| match token {
+                    Token::Token(value) => value.as_str(),
+                    Token::Boundary => "<boundary>",
+                })
+                .collect();
+            writeln!(f, "  {:>8}  {}", count, tokens.join(" | "))?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Sort most common first, breaking ties by the item so the order is stable
+fn sort_by_count<I: Ord>(items: &mut [(I, f64)]) {
+    items.sort_by(|(a_item, a_count), (b_item, b_count)| {
+        b_count.total_cmp(a_count).then_with(|| a_item.cmp(b_item))
+    });
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::train::train_with_tokens;
+
+    #[test]
+    fn test_model_stats() {
+        let mut transitions = Transitions::new();
+        for tokens in [vec!["Call", "me"], vec!["Call", "me"], vec!["Call", "Ishmael"], vec!["me"]] {
+            let mut line = vec![Token::Boundary];
+            line.extend(tokens.into_iter().map(Token::from));
+            line.push(Token::Boundary);
+            train_with_tokens(line, &mut transitions);
+        }
+        // Leads to a token with no transitions of its own
+        transitions.count_transition(&Token::from("me"), &Token::from("overboard"));
+
+        let stats = ModelStats::from_transitions(&transitions, 2, |_, token| token.clone());
+
+        assert_eq!(stats.vocabulary, 4);
+        assert_eq!(stats.states, 4);
+        assert_eq!(stats.total_transitions, 12.0);
+        assert_eq!(stats.distinct_transitions, 7);
+        assert_eq!(stats.branching_factor, 7.0 / 4.0);
+        assert_eq!(stats.count_histogram, vec![(1, 1, 4), (2, 3, 3)]);
+        assert_eq!(stats.top_start_tokens, vec![("Call".to_string(), 3.0), ("me".to_string(), 1.0)]);
+        assert_eq!(stats.dead_ends, vec!["overboard".to_string()]);
+        assert_eq!(stats.top_ngrams, vec![
+            (vec![Token::from("me"), Token::Boundary], 3.0),
+            (vec![Token::Boundary, Token::from("Call")], 3.0),
+        ]);
+    }
+}
@@ -1,6 +1,9 @@
-#[derive(Eq, Hash, Debug, Clone)]
-pub enum Token {
-    Token(String),
+/// A symbol in a chain, or the boundary sentinel marking the start/end of a sequence
+///
+/// Text chains use the default `String` symbols, but any `T: Hash + Eq + Clone` works
+#[derive(PartialEq, Eq, PartialOrd, Ord, Hash, Debug, Clone)]
+pub enum Token<T = String> {
+    Token(T),
     Boundary,
 }
 
@@ -8,11 +11,11 @@ pub enum Token {
 use memuse::DynamicUsage;
 
 #[cfg(feature = "memory-profiling")]
-impl DynamicUsage for Token {
+impl<T: DynamicUsage> DynamicUsage for Token<T> {
     fn dynamic_usage(&self) -> usize {
         match self {
-            Token::Token(s) => s.capacity(),
-            Token::Boundary => std::mem::size_of::<Token>(),
+            Token::Token(s) => s.dynamic_usage(),
+            Token::Boundary => std::mem::size_of::<Token<T>>(),
         }
     }
     
@@ -22,18 +25,53 @@ impl DynamicUsage for Token {
     }
 }
 
-impl Token {
-    pub fn from<S: Into<String>>(value: S) -> Token {
-        Token::Token(value.into())
+/// Text tokens serialize as their string, with a Token::Boundary as the empty string (which no
+/// tokenizer produces)
+#[cfg(feature = "serde")]
+impl serde::Serialize for Token {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self {
+            Token::Token(value) => serializer.serialize_str(value),
+            Token::Boundary => serializer.serialize_str(""),
+        }
     }
 }
 
-impl PartialEq for Token {
-    fn eq(&self, other: &Token) -> bool {
-        match (self, other) {
-            (Token::Token(s), Token::Token(o)) => s == o,
-            (Token::Boundary, Token::Boundary) => true,
-            _ => false,
+/// Reads a Token::Boundary from either the empty string or a null
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for Token {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct TokenVisitor;
+
+        impl serde::de::Visitor<'_> for TokenVisitor {
+            type Value = Token;
+
+            fn expecting(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+                f.write_str("a token string, or an empty string or null for a boundary")
+            }
+
+            fn visit_str<E: serde::de::Error>(self, value: &str) -> Result<Token, E> {
+                match value {
+                    "" => Ok(Token::Boundary),
+                    value => Ok(Token::from(value)),
+                }
+            }
+
+            fn visit_unit<E: serde::de::Error>(self) -> Result<Token, E> {
+                Ok(Token::Boundary)
+            }
+
+            fn visit_none<E: serde::de::Error>(self) -> Result<Token, E> {
+                Ok(Token::Boundary)
+            }
         }
+
+        deserializer.deserialize_any(TokenVisitor)
+    }
+}
+
+impl Token {
+    pub fn from<S: Into<String>>(value: S) -> Token {
+        Token::Token(value.into())
     }
 }
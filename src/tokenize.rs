@@ -2,7 +2,9 @@
 //!
 //! Contains logic for tokenizing strings
 
-use crate::{token::Token, BoundaryConfigs};
+use unicode_segmentation::UnicodeSegmentation;
+
+use crate::{token::Token, BoundaryConfigs, TokenizerConfigs};
 
 
 const SENTENCE_ENDINGS: [char; 3] = ['.', '!', '?'];
@@ -14,7 +16,7 @@ const PUNCTUATION_BEGININGS: [char; 4] = ['"', '\'', '{', '('];
 /// as a vector of tokens
 pub fn tokenize(line: &str, boundary_config: &BoundaryConfigs) -> impl Iterator<Item = Token> {
     // Start with just splitting on whitespace
-    let mut tokens: Vec<Token> = line.split_whitespace().map(|s| Token::from(s)).collect();
+    let mut tokens: Vec<Token> = line.split_whitespace().map(Token::from).collect();
     if let BoundaryConfigs::SentenceEndings = boundary_config {
         split_out_sentence_boundaries(&mut tokens);
     }
@@ -24,6 +26,27 @@ pub fn tokenize(line: &str, boundary_config: &BoundaryConfigs) -> impl Iterator<
     tokens.into_iter()
 }
 
+/// Takes an input line of text, returns each whitespace-separated word broken up into
+/// character (or grapheme cluster) tokens, followed by a Token::Boundary
+/// `"ab c"` -> `["a", "b", Token::Boundary, "c", Token::Boundary]`
+pub fn tokenize_characters(line: &str, tokenizer_config: &TokenizerConfigs) -> impl Iterator<Item = Token> {
+    let mut tokens: Vec<Token> = Vec::new();
+    for word in line.split_whitespace() {
+        match tokenizer_config {
+            TokenizerConfigs::Graphemes => tokens.extend(word.graphemes(true).map(Token::from)),
+            _ => tokens.extend(word.chars().map(Token::from)),
+        }
+        tokens.push(Token::Boundary);
+    }
+
+    tokens.into_iter()
+}
+
+/// Whether a word ends in punctuation that ends a sentence
+pub(crate) fn ends_sentence(word: &str) -> bool {
+    word.chars().last().is_some_and(|last_char| SENTENCE_ENDINGS.contains(&last_char))
+}
+
 /// Splits out tokens with sentence boundaries
 /// `["man."]` -> `["man", Token::Boundary]`
 fn split_out_sentence_boundaries(tokens: &mut Vec<Token>) {
@@ -31,21 +54,20 @@ fn split_out_sentence_boundaries(tokens: &mut Vec<Token>) {
     let mut insertions: Vec<(usize, Vec<Token>)> = Vec::new();
     
     for (i, token) in tokens.iter().enumerate() {
-        if let Token::Token(value) = token {
-            if let Some(last_char) = value.chars().last() {
-                if SENTENCE_ENDINGS.contains(&last_char) {
-                    // Create the token without the sentence ending
-                    let mut new_tokens: Vec<Token> = Vec::new();
-                    // If the value was only one char (i.e. ".") we'll end up adding a blank token ""
-                    // so we only add the trimmed version if it's longer than 1
-                    if value.len() > 1 {
-                        let trimmed_value: String = value.chars().take(value.len() - 1).collect();
-                        new_tokens.push(Token::Token(trimmed_value));
-                    }
-                    new_tokens.push(Token::Boundary);
-                    insertions.push((i, new_tokens));
-                }
+        if let Token::Token(value) = token
+            && let Some(last_char) = value.chars().last()
+            && SENTENCE_ENDINGS.contains(&last_char)
+        {
+            // Create the token without the sentence ending
+            let mut new_tokens: Vec<Token> = Vec::new();
+            // If the value was only one char (i.e. ".") we'll end up adding a blank token ""
+            // so we only add the trimmed version if it's longer than 1
+            if value.len() > 1 {
+                let trimmed_value: String = value.chars().take(value.len() - 1).collect();
+                new_tokens.push(Token::Token(trimmed_value));
             }
+            new_tokens.push(Token::Boundary);
+            insertions.push((i, new_tokens));
         }
     }
     
@@ -66,19 +88,18 @@ fn split_out_punctuation_endings(tokens: &mut Vec<Token>) {
     let mut insertions: Vec<(usize, Vec<Token>)> = Vec::new();
     
     for (i, token) in tokens.iter().enumerate() {
-        if let Token::Token(value) = token {
-            if let Some(last_char) = value.chars().last() {
-                if PUNCTUATION_ENDINGS.contains(&last_char) {
-                    let mut new_tokens: Vec<Token> = Vec::new();
-                    if value.len() > 1 {
-                        // Create the token without the punctuation
-                        let trimmed_value: String = value.chars().take(value.len() - 1).collect();
-                        new_tokens.push(Token::from(trimmed_value));
-                    }
-                    new_tokens.push(Token::from(last_char));
-                    insertions.push((i, new_tokens));
-                }
+        if let Token::Token(value) = token
+            && let Some(last_char) = value.chars().last()
+            && PUNCTUATION_ENDINGS.contains(&last_char)
+        {
+            let mut new_tokens: Vec<Token> = Vec::new();
+            if value.len() > 1 {
+                // Create the token without the punctuation
+                let trimmed_value: String = value.chars().take(value.len() - 1).collect();
+                new_tokens.push(Token::from(trimmed_value));
             }
+            new_tokens.push(Token::from(last_char));
+            insertions.push((i, new_tokens));
         }
     }
     
@@ -101,17 +122,17 @@ fn split_out_punctuation_beginings(tokens: &mut Vec<Token>) {
     for (i, token) in tokens.iter().enumerate() {
         if let Token::Token(value) = token {
             let mut value_chars = value.chars();
-            if let Some(first_char) = value_chars.next() {
-                if PUNCTUATION_BEGININGS.contains(&first_char) {
-                    let mut new_tokens = vec![Token::from(first_char)];
-                    if value.len() > 1 {
-                        // Create the token without the punctuation
-                        // The first char is already iterated
-                        let trimmed_value: String = value_chars.collect();
-                        new_tokens.push(Token::from(trimmed_value));
-                    }
-                    insertions.push((i, new_tokens));
+            if let Some(first_char) = value_chars.next()
+                && PUNCTUATION_BEGININGS.contains(&first_char)
+            {
+                let mut new_tokens = vec![Token::from(first_char)];
+                if value.len() > 1 {
+                    // Create the token without the punctuation
+                    // The first char is already iterated
+                    let trimmed_value: String = value_chars.collect();
+                    new_tokens.push(Token::from(trimmed_value));
                 }
+                insertions.push((i, new_tokens));
             }
         }
     }
@@ -177,6 +198,30 @@ mod tests {
         )
     }
 
+    #[test]
+    fn test_tokenize_characters() {
+        let input = "Ahab e\u{301}";
+        let output: Vec<Token> = vec![
+            Token::from("A"),
+            Token::from("h"),
+            Token::from("a"),
+            Token::from("b"),
+            Token::Boundary,
+            Token::from("e"),
+            Token::from("\u{301}"),
+            Token::Boundary,
+        ];
+        assert_eq!(
+            output,
+            tokenize_characters(input, &TokenizerConfigs::Characters).collect::<Vec<Token>>(),
+        );
+
+        // Graphemes keep the combining accent with its letter
+        let tokenized: Vec<Token> = tokenize_characters(input, &TokenizerConfigs::Graphemes).collect();
+        assert_eq!(tokenized.len(), 7);
+        assert_eq!(tokenized[5], Token::from("e\u{301}"));
+    }
+
     #[test]
     fn test_split_out_sentence_boundaries() {
         // Level 1: Easy
@@ -286,4 +331,4 @@ mod tests {
             "Should split left parens and quotes"
         );
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,62 @@
+//! trace module
+//!
+//! Contains the per-step record of how a sentence was generated, for debugging models
+
+use crate::json::token_json;
+use crate::token::Token;
+
+
+/// One step of generation
+#[derive(Debug, Clone, PartialEq)]
+pub struct TraceStep {
+    /// The state the token was picked from
+    pub state: Token,
+    /// The token picked, a Token::Boundary ends the sentence
+    pub token: Token,
+    /// Probability the token had of being picked, at the temperature it was picked with
+    pub probability: f64,
+    /// Number of tokens that could have been picked
+    pub candidates: usize,
+    /// Whether the state was unknown, so a shorter history was used to pick the token
+    pub backoff: bool,
+    /// Whether no state had any tokens to pick from, so generation had to start over
+    pub restart: bool,
+}
+
+impl TraceStep {
+    /// Render the step as a single line JSON object, with a Token::Boundary as `null`
+    pub fn to_json(&self) -> String {
+        format!(
+            "{{\"state\":{},\"token\":{},\"probability\":{},\"candidates\":{},\"backoff\":{},\"restart\":{}}}",
+            token_json(&self.state),
+            token_json(&self.token),
+            self.probability,
+            self.candidates,
+            self.backoff,
+            self.restart,
+        )
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_trace_step_to_json() {
+        let step = TraceStep {
+            state: Token::Boundary,
+            token: Token::from("\"Call\""),
+            probability: 0.25,
+            candidates: 4,
+            backoff: false,
+            restart: false,
+        };
+
+        assert_eq!(
+            step.to_json(),
+            r#"{"state":null,"token":"\"Call\"","probability":0.25,"candidates":4,"backoff":false,"restart":false}"#,
+        );
+    }
+}
@@ -1,53 +1,99 @@
 //! train module
 //!
 //! Contains logic for training the transitions for token prediction
+use std::hash::Hash;
 use std::io::BufRead;
 
+use crate::context::Context;
+use crate::decode::Decoder;
+use crate::error::Error;
 use crate::token::Token;
-use crate::tokenize::tokenize;
+use crate::tokenize::{tokenize, tokenize_characters};
 use crate::transitions::Transitions;
-use crate::BoundaryConfigs;
+use crate::{BoundaryConfigs, TokenizerConfigs};
 
 
-/// Read lines from buffer and train on token transitions
+/// Read lines from buffer and train on word token transitions
+///
+/// Stops at the first line that can't be read, having trained on the lines before it. Lines that
+/// aren't valid UTF-8 are skipped.
 pub fn train_with_stream<'a, R: BufRead>(
     input: R, transitions: &'a mut Transitions, boundary_config: &BoundaryConfigs
-) -> &'a mut Transitions {
+) -> Result<&'a mut Transitions, Error> {
+    train_with_tokenizer(
+        input, transitions, boundary_config, &TokenizerConfigs::Words, 1, &mut Decoder::default()
+    )
+}
+
+/// Read lines from buffer and train on token transitions, using the given tokenizer and
+/// chain order, decoding the lines with decoder
+///
+/// If the transitions are recording provenance, the lines are recorded as coming from a
+/// document named "-"
+pub fn train_with_tokenizer<'a, R: BufRead>(
+    input: R,
+    transitions: &'a mut Transitions,
+    boundary_config: &BoundaryConfigs,
+    tokenizer_config: &TokenizerConfigs,
+    order: usize,
+    decoder: &mut Decoder,
+) -> Result<&'a mut Transitions, Error> {
+    // The context is carried from the end of one line to the beginning of the next
+    let mut context = Context::new(order, tokenizer_config);
+    let lines = tokenize_stream(input, boundary_config, tokenizer_config, decoder);
+    for (i, tokens) in lines.enumerate() {
+        transitions.set_source("-", i + 1);
+        train_with_context(tokens?, transitions, &mut context, 1.0);
+    }
+
+    log_memory_usage(transitions);
+
+    Ok(transitions)
+}
+
+/// Read lines from buffer and tokenize each one
+///
+/// If line-endings are our boundaries, each line of tokens is wrapped in Token::Boundary, so
+/// a context fed with them starts over on every line
+///
+/// The lines are read as raw bytes and decoded by the decoder, so lines it can't decode are
+/// errors, carrying the number of the line
+pub fn tokenize_stream<'a, R: BufRead + 'a>(
+    input: R,
+    boundary_config: &'a BoundaryConfigs,
+    tokenizer_config: &'a TokenizerConfigs,
+    decoder: &'a mut Decoder,
+) -> impl Iterator<Item = Result<Vec<Token>, Error>> + 'a {
+    tokenize_lines(decoder.decode_lines(input), boundary_config, tokenizer_config)
+}
 
-    // We don't really care about breaking this up into lines, but going lower-level would mean
-    // messing with reading raw bytes out of the buffer, just to reconstruct them back into utf-8
-    // which would be tedious and inefficient.
-    // Instead, we'll read strings out of the buffer, line-by-line, then stitch the end of one
-    // line to the beginning of the next
-    let mut last_token: Token = Token::Boundary;
-    for line_res in input.lines() {
+/// Tokenize each of the lines given, passing errors through, such as lines already decoded and
+/// preprocessed
+///
+/// Line breaks within a line, which end the blocks of text extracted from markup, are boundaries
+pub fn tokenize_lines<'a, I: Iterator<Item = Result<String, Error>> + 'a>(
+    lines: I,
+    boundary_config: &'a BoundaryConfigs,
+    tokenizer_config: &'a TokenizerConfigs,
+) -> impl Iterator<Item = Result<Vec<Token>, Error>> + 'a {
+    lines.map(move |line_res| {
+        let line = line_res?;
         let mut tokens: Vec<Token> = Vec::new();
 
         // This is the beginning of a new line so, if line-endings are our boundaries, push a Token::Boundary
         if let BoundaryConfigs::LineEndings = boundary_config {
             tokens.push(Token::Boundary);
-        } else {
-            // Otherwise, preserve the transition from the last line by pushing last_token
-            tokens.push(last_token.clone());
         }
 
-        match line_res {
-            Ok(line) => {
-                tokens.extend(tokenize(&line, boundary_config));
-
-                if let BoundaryConfigs::SentenceEndings = boundary_config {
-                    // Save the last token for the next line
-                    let tokens_len = tokens.len();
-                    if tokens_len > 0 {
-                        last_token = match tokens.get(tokens_len-1) {
-                            Some(t) => t.clone(),
-                            None => Token::Boundary
-                        };
-                    }
-                }
-            },
-            Err(e) => {
-                eprintln!("Error reading line: {}", e);
+        // Text extracted from markup ends each block (like a heading or paragraph) in a line
+        // break, which is a boundary whatever our boundaries are
+        for block in line.split_inclusive('\n') {
+            match tokenizer_config {
+                TokenizerConfigs::Words => tokens.extend(tokenize(block, boundary_config)),
+                _ => tokens.extend(tokenize_characters(block, tokenizer_config)),
+            }
+            if block.ends_with('\n') && tokens.last().is_some_and(|token| *token != Token::Boundary) {
+                tokens.push(Token::Boundary);
             }
         }
 
@@ -56,27 +102,31 @@ pub fn train_with_stream<'a, R: BufRead>(
             tokens.push(Token::Boundary);
         }
 
-        train_with_tokens(tokens, transitions);
-    }
+        Ok(tokens)
+    })
+}
 
-    // Log memory usage when memory-profiling feature is enabled
+/// Log memory usage when memory-profiling feature is enabled
+pub fn log_memory_usage(_transitions: &Transitions) {
     #[cfg(feature = "memory-profiling")]
     {
         use memuse::DynamicUsage;
-        let estimated_size = transitions.dynamic_usage();
+        let estimated_size = _transitions.dynamic_usage();
         eprintln!(
             "Estimated transitions HashMap memory usage: {} bytes ({:.2} MB)", 
             estimated_size,
             estimated_size as f64 / 1_048_576.0
         );
     }
-
-    transitions
 }
 
 /// Input tokens and add transitions to existing map
 ///
-/// transitions should look like:
+/// If the transitions are counting backward transitions, each transition is also counted from
+/// next token back to last token
+///
+/// transitions should look like this, which is also the shape `Transitions::to_json` exports,
+/// with a Token::Boundary written as `""`:
 /// ```json
 /// {
 ///     "the": {
@@ -91,9 +141,9 @@ pub fn train_with_stream<'a, R: BufRead>(
 ///     }
 /// }
 /// ```
-pub fn train_with_tokens(
-    tokens: Vec<Token>, transitions: &mut Transitions 
-) -> &mut Transitions {
+pub fn train_with_tokens<T: Hash + Eq + Clone>(
+    tokens: Vec<Token<T>>, transitions: &mut Transitions<T>
+) -> &mut Transitions<T> {
     let mut tokens_iter = tokens.iter();
 
     // Get the first token
@@ -107,7 +157,10 @@ pub fn train_with_tokens(
         match (&last_token, next_token) {
             // Specifically suppress Boundary->Boundary transitions caused by things like empty lines
             (Token::Boundary, Token::Boundary) => (),
-            _ => transitions.count_transition(&last_token, next_token)
+            _ => {
+                transitions.count_transition(&last_token, next_token);
+                transitions.count_backward_transition(next_token, &last_token);
+            }
         };
 
         // Shift next to last for next iteration
@@ -117,11 +170,58 @@ pub fn train_with_tokens(
     transitions
 }
 
+/// Input text tokens and add transitions from the state of the context to each token, shifting
+/// each token into the context as we go, adding weight to each count
+///
+/// With an order 1 context and a weight of 1 this counts the same transitions as
+/// `train_with_tokens`
+pub fn train_with_context<'a, I: IntoIterator<Item = Token>>(
+    tokens: I, transitions: &'a mut Transitions, context: &mut Context, weight: f64
+) -> &'a mut Transitions {
+    for next_token in tokens {
+        match (context.state(), &next_token) {
+            // Specifically suppress Boundary->Boundary transitions caused by things like empty lines
+            (Token::Boundary, Token::Boundary) => (),
+            (state, _) => {
+                transitions.count_weighted_transition(&state, &next_token, weight);
+                // Backward transitions only look back one token
+                transitions.count_weighted_backward_transition(&next_token, &context.last_token(), weight);
+            }
+        };
+
+        // Shift next into the context for the next iteration
+        context.push(&next_token);
+    }
+
+    transitions
+}
+
+/// Input text tokens and subtract the transitions `train_with_context` would have added, so a
+/// document trained with weight can be taken back out of a model
+pub fn untrain_with_context<'a, I: IntoIterator<Item = Token>>(
+    tokens: I, transitions: &'a mut Transitions, context: &mut Context, weight: f64
+) -> &'a mut Transitions {
+    for next_token in tokens {
+        match (context.state(), &next_token) {
+            (Token::Boundary, Token::Boundary) => (),
+            (state, _) => {
+                transitions.uncount_transition(&state, &next_token, weight);
+                transitions.uncount_backward_transition(&next_token, &context.last_token(), weight);
+            }
+        };
+
+        context.push(&next_token);
+    }
+
+    transitions
+}
+
 
 #[cfg(test)]
 mod tests {
     use std::{collections::HashMap, io::Cursor};
     use super::*;
+    use crate::{EncodingConfigs, InvalidUtf8Configs};
 
 
     #[test]
@@ -133,7 +233,7 @@ mod tests {
         ");
 
         let mut transitions = Transitions::new();
-        train_with_stream(input, &mut transitions, &BoundaryConfigs::LineEndings);
+        train_with_stream(input, &mut transitions, &BoundaryConfigs::LineEndings).unwrap();
 
         assert_eq!(
             transitions,
@@ -168,7 +268,7 @@ mod tests {
         ");
 
         let mut transitions = Transitions::new();
-        train_with_stream(input, &mut transitions, &BoundaryConfigs::SentenceEndings);
+        train_with_stream(input, &mut transitions, &BoundaryConfigs::SentenceEndings).unwrap();
 
         assert_eq!(
             transitions,
@@ -192,6 +292,31 @@ mod tests {
         )
     }
 
+    #[test]
+    fn test_train_characters_with_order() {
+        let input = Cursor::new("abc ab");
+
+        let mut transitions = Transitions::new();
+        train_with_tokenizer(
+            input,
+            &mut transitions,
+            &BoundaryConfigs::LineEndings,
+            &TokenizerConfigs::Characters,
+            2,
+            &mut Decoder::default(),
+        ).unwrap();
+
+        assert_eq!(
+            transitions,
+            HashMap::from([
+                (Token::Boundary, HashMap::from([(Token::from("a"), 2)])),
+                (Token::from("a"), HashMap::from([(Token::from("b"), 2)])),
+                (Token::from("ab"), HashMap::from([(Token::from("c"), 1), (Token::Boundary, 1)])),
+                (Token::from("bc"), HashMap::from([(Token::Boundary, 1)])),
+            ])
+        )
+    }
+
     #[test]
     fn test_train_with_tokens_populates_transitions_map() {
         let mut transitions = Transitions::new();
@@ -220,4 +345,80 @@ mod tests {
             ])
         );
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_tokenize_stream_decodes_lines() {
+        let mut decoder = Decoder::new(EncodingConfigs::Utf8, InvalidUtf8Configs::Skip);
+        let input = Cursor::new(b"one\r\ntw\xffo\rthree");
+        let tokenized: Vec<Vec<Token>> = tokenize_stream(
+            input, &BoundaryConfigs::SentenceEndings, &TokenizerConfigs::Words, &mut decoder
+        ).map(Result::unwrap).collect();
+
+        assert_eq!(tokenized, vec![vec![Token::from("one")], vec![], vec![Token::from("three")]]);
+        assert_eq!(decoder.stats().skipped_lines, 1);
+        assert_eq!(decoder.stats().cr_endings, 1);
+
+        let mut lossy = Decoder::new(EncodingConfigs::Utf8, InvalidUtf8Configs::Lossy);
+        let input = Cursor::new(b"one\r\ntw\xffo\rthree");
+        let tokenized: Vec<Result<Vec<Token>, Error>> = tokenize_stream(
+            input, &BoundaryConfigs::SentenceEndings, &TokenizerConfigs::Words, &mut lossy
+        ).collect();
+        assert_eq!(tokenized[1].as_ref().unwrap(), &vec![Token::from("tw\u{FFFD}o")]);
+
+        let mut abort = Decoder::new(EncodingConfigs::Utf8, InvalidUtf8Configs::Abort);
+        let input = Cursor::new(b"one\r\ntw\xffo\rthree");
+        let tokenized: Vec<Result<Vec<Token>, Error>> = tokenize_stream(
+            input, &BoundaryConfigs::SentenceEndings, &TokenizerConfigs::Words, &mut abort
+        ).collect();
+        assert!(tokenized[0].is_ok());
+        assert_eq!(tokenized[1].as_ref().unwrap_err().line(), Some(2));
+    }
+
+    #[test]
+    fn test_tokenize_lines_ends_blocks_in_boundaries() {
+        let lines = ["Loomings\nCall me Ishmael\n", "", "Some years ago"].map(|line| Ok(line.to_string()));
+        let tokenized: Vec<Vec<Token>> = tokenize_lines(
+            lines.into_iter(), &BoundaryConfigs::SentenceEndings, &TokenizerConfigs::Words
+        ).map(Result::unwrap).collect();
+
+        assert_eq!(tokenized, vec![
+            vec![
+                Token::from("Loomings"), Token::Boundary,
+                Token::from("Call"), Token::from("me"), Token::from("Ishmael"), Token::Boundary,
+            ],
+            vec![],
+            vec![Token::from("Some"), Token::from("years"), Token::from("ago")],
+        ]);
+    }
+
+    #[test]
+    fn test_train_stops_at_invalid_utf8() {
+        let input = b"a b\nc \xff d\ne f";
+        let mut transitions = Transitions::new();
+        let error = train_with_tokenizer(
+            Cursor::new(input),
+            &mut transitions,
+            &BoundaryConfigs::LineEndings,
+            &TokenizerConfigs::Words,
+            1,
+            &mut Decoder::new(EncodingConfigs::Utf8, InvalidUtf8Configs::Abort),
+        ).unwrap_err();
+
+        assert!(matches!(error, Error::InvalidUtf8 { line: 2 }), "{}", error);
+        // The lines before the error are still trained on
+        assert_eq!(
+            transitions,
+            HashMap::from([
+                (Token::Boundary, HashMap::from([(Token::from("a"), 1)])),
+                (Token::from("a"), HashMap::from([(Token::from("b"), 1)])),
+                (Token::from("b"), HashMap::from([(Token::Boundary, 1)])),
+            ])
+        );
+
+        // Unless invalid lines are skipped, as they are to start with
+        let mut transitions = Transitions::new();
+        train_with_stream(Cursor::new(input), &mut transitions, &BoundaryConfigs::LineEndings).unwrap();
+        assert!(transitions.next_tokens(&Token::from("e")).is_some());
+        assert!(transitions.next_tokens(&Token::from("c")).is_none());
+    }
+}
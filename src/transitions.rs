@@ -1,30 +1,57 @@
-use std::{collections::HashMap};
+use std::collections::HashMap;
+#[cfg(feature = "serde")]
+use std::collections::BTreeMap;
+use std::hash::Hash;
+use crate::provenance::{Location, Provenance};
 use crate::token::Token;
 
 
+/// Transition counts from one token to each of the tokens that follow it
+///
+/// Counts are fractional, so they can be decayed and weighted
+type TransitionMap<T> = HashMap<Token<T>, HashMap<Token<T>, f64>>;
+
+/// Counts that fall below this, from decay or untraining, are dropped as all but gone
+pub const MIN_COUNT: f64 = 0.01;
+
+/// Whole number counts, for comparing with transitions counted without decay or weights
+type WholeTransitionMap<T> = HashMap<Token<T>, HashMap<Token<T>, u32>>;
+
 /// Token transitions training container
 /// Counts transitions between tokens for a training corpus
-#[derive(Eq, Debug)]
-pub struct Transitions {
-    transitions: HashMap<Token, HashMap<Token, u32>>,
+#[derive(Debug)]
+pub struct Transitions<T = String> {
+    transitions: TransitionMap<T>,
+    /// Optional reverse counts, from next_token back to last_token, for generating backward
+    backward: Option<TransitionMap<T>>,
+    /// Optional record of where each transition was counted
+    provenance: Option<Provenance<T>>,
 }
 
 /// Allows equality comparison to a raw HashMap container, for easier testing
-impl PartialEq for Transitions {
+impl<T: Hash + Eq> PartialEq for Transitions<T> {
     fn eq(&self, other: &Self) -> bool {
         self.transitions == other.transitions
+            && self.backward == other.backward
+            && self.provenance == other.provenance
     }
 }
 
-impl PartialEq<HashMap<Token, HashMap<Token, u32>>> for Transitions {
-    fn eq(&self, other: &HashMap<Token, HashMap<Token, u32>>) -> bool {
-        self.transitions == *other
+impl<T: Hash + Eq> PartialEq<WholeTransitionMap<T>> for Transitions<T> {
+    fn eq(&self, other: &WholeTransitionMap<T>) -> bool {
+        self.transitions.len() == other.len()
+            && other.iter().all(|(last_token, next_tokens)| {
+                self.transitions.get(last_token).is_some_and(|counts| {
+                    counts.len() == next_tokens.len()
+                        && next_tokens.iter().all(|(token, count)| counts.get(token) == Some(&(*count as f64)))
+                })
+            })
     }
 }
 
-impl PartialEq<Transitions> for HashMap<Token, HashMap<Token, u32>> {
-    fn eq(&self, other: &Transitions) -> bool {
-        *self == other.transitions
+impl<T: Hash + Eq> PartialEq<Transitions<T>> for WholeTransitionMap<T> {
+    fn eq(&self, other: &Transitions<T>) -> bool {
+        other == self
     }
 }
 
@@ -32,51 +59,390 @@ impl PartialEq<Transitions> for HashMap<Token, HashMap<Token, u32>> {
 use memuse::DynamicUsage;
 
 #[cfg(feature = "memory-profiling")]
-impl DynamicUsage for Transitions {
+impl<T: DynamicUsage> DynamicUsage for Transitions<T> {
     fn dynamic_usage(&self) -> usize {
         self.transitions.dynamic_usage()
+            + self.backward.dynamic_usage()
+            + self.provenance.dynamic_usage()
     }
     
     fn dynamic_usage_bounds(&self) -> (usize, Option<usize>) {
-        self.transitions.dynamic_usage_bounds()
+        let (forward_lower, forward_upper) = self.transitions.dynamic_usage_bounds();
+        let (backward_lower, backward_upper) = self.backward.dynamic_usage_bounds();
+        let (provenance_lower, provenance_upper) = self.provenance.dynamic_usage_bounds();
+        (
+            forward_lower + backward_lower + provenance_lower,
+            forward_upper.zip(backward_upper).zip(provenance_upper).map(|((f, b), p)| f + b + p),
+        )
     }
 }
 
-impl Transitions {
+impl<T: Hash + Eq + Clone> Default for Transitions<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T: Hash + Eq + Clone> Transitions<T> {
     /// Construct a new, empty Transitions container
-    pub fn new() -> Transitions {
+    pub fn new() -> Transitions<T> {
+        Transitions {
+            transitions: HashMap::new(),
+            backward: None,
+            provenance: None,
+        }
+    }
+
+    /// Construct a new, empty Transitions container that also counts transitions backward
+    pub fn with_backward() -> Transitions<T> {
         Transitions {
-            transitions: HashMap::new()
+            transitions: HashMap::new(),
+            backward: Some(HashMap::new()),
+            provenance: None,
+        }
+    }
+
+    /// Start counting backward transitions, for transitions counted from now on
+    pub fn enable_backward(&mut self) {
+        if self.backward.is_none() {
+            self.backward = Some(HashMap::new());
+        }
+    }
+
+    /// Whether backward transitions are being counted
+    pub fn has_backward(&self) -> bool {
+        self.backward.is_some()
+    }
+
+    /// Start recording where transitions are counted, for transitions counted from now on
+    pub fn enable_provenance(&mut self) {
+        if self.provenance.is_none() {
+            self.provenance = Some(Provenance::new());
+        }
+    }
+
+    /// Whether the locations transitions are counted in are being recorded
+    pub fn has_provenance(&self) -> bool {
+        self.provenance.is_some()
+    }
+
+    /// Set the location transitions counted from now on come from, if recording provenance
+    pub fn set_source(&mut self, document: &str, line: usize) {
+        if let Some(provenance) = &mut self.provenance {
+            provenance.set_source(document, line);
         }
     }
 
     /// Add the last_token to next_token to the transitions count training data
-    pub fn count_transition(&mut self, last_token: &Token, next_token: &Token) {
-        // Get collected transitions from last_token
-        let token_trans = self.transitions
-            .entry(last_token.clone())
-            .or_insert_with(HashMap::new);
+    pub fn count_transition(&mut self, last_token: &Token<T>, next_token: &Token<T>) {
+        self.count_weighted_transition(last_token, next_token, 1.0);
+    }
+
+    /// Add the last_token to next_token to the transitions count training data, adding weight
+    /// to its count instead of 1, such as to weight a small corpus up against a large one
+    pub fn count_weighted_transition(&mut self, last_token: &Token<T>, next_token: &Token<T>, weight: f64) {
+        count(&mut self.transitions, last_token, next_token, weight);
+        if let Some(provenance) = &mut self.provenance {
+            provenance.record(last_token, next_token);
+        }
+    }
+
+    /// Add the next_token back to last_token to the backward transitions count training data, if
+    /// backward transitions are being counted
+    pub fn count_backward_transition(&mut self, next_token: &Token<T>, last_token: &Token<T>) {
+        self.count_weighted_backward_transition(next_token, last_token, 1.0);
+    }
+
+    /// Add the next_token back to last_token to the backward transitions count training data
+    /// with a weight, if backward transitions are being counted
+    pub fn count_weighted_backward_transition(
+        &mut self, next_token: &Token<T>, last_token: &Token<T>, weight: f64
+    ) {
+        if let Some(backward) = &mut self.backward {
+            count(backward, next_token, last_token, weight);
+        }
+    }
+
+    /// Subtract the last_token to next_token transition, counted with weight, from the
+    /// transitions count training data, dropping it once its count is all but gone
+    ///
+    /// Provenance is left as it is
+    pub fn uncount_transition(&mut self, last_token: &Token<T>, next_token: &Token<T>, weight: f64) {
+        if self.transitions.get(last_token).is_some_and(|next| next.contains_key(next_token)) {
+            count(&mut self.transitions, last_token, next_token, -weight);
+        }
+    }
+
+    /// Subtract the next_token back to last_token, counted with weight, from the backward
+    /// transitions count training data, if backward transitions are being counted
+    pub fn uncount_backward_transition(&mut self, next_token: &Token<T>, last_token: &Token<T>, weight: f64) {
+        if let Some(backward) = &mut self.backward
+            && backward.get(next_token).is_some_and(|last| last.contains_key(last_token))
+        {
+            count(backward, next_token, last_token, -weight);
+        }
+    }
 
-        // Add 1 to the transition to next_token
-        token_trans.entry(next_token.clone())
-            .and_modify(|p| { *p += 1 })
-            .or_insert(1);
+    /// Scale every count down by factor (between 0 and 1), so older training fades compared to
+    /// training done afterward. Counts that fall below MIN_COUNT are dropped.
+    pub fn decay(&mut self, factor: f64) {
+        decay(&mut self.transitions, factor);
+        if let Some(backward) = &mut self.backward {
+            decay(backward, factor);
+        }
+    }
+
+    /// Add the counts of other transitions to these, scaled by weight, such as to combine models
+    /// trained separately. Backward transitions are only merged if both have them.
+    pub fn merge(&mut self, other: &Transitions<T>, weight: f64) {
+        merge(&mut self.transitions, &other.transitions, weight);
+        if let (Some(backward), Some(other_backward)) = (&mut self.backward, &other.backward) {
+            merge(backward, other_backward, weight);
+        }
     }
 
     /// Retrieve all last_tokens as an iterator
-    pub fn last_tokens(&self) -> impl Iterator<Item = &Token> {
+    pub fn last_tokens(&self) -> impl Iterator<Item = &Token<T>> {
         self.transitions.keys()
     }
 
     /// Get next token transition counts
-    pub fn next_tokens(&self, last_token: &Token) -> Option<&HashMap<Token, u32>> {
+    pub fn next_tokens(&self, last_token: &Token<T>) -> Option<&HashMap<Token<T>, f64>> {
         self.transitions.get(last_token)
     }
 
     /// Get the Start transition counts
-    pub fn start_tokens(&self) -> Option<&HashMap<Token, u32>> {
+    pub fn start_tokens(&self) -> Option<&HashMap<Token<T>, f64>> {
         self.transitions.get(&Token::Boundary)
     }
+
+    /// Get previous token transition counts, if backward transitions are being counted
+    pub fn previous_tokens(&self, next_token: &Token<T>) -> Option<&HashMap<Token<T>, f64>> {
+        self.backward.as_ref()?.get(next_token)
+    }
+
+    /// Get the locations the last_token to next_token transition was counted in, if recording
+    /// provenance
+    pub fn sources(&self, last_token: &Token<T>, next_token: &Token<T>) -> Option<Vec<Location>> {
+        Some(self.provenance.as_ref()?.locations(last_token, next_token))
+    }
+
+    /// Get the End transition counts, if backward transitions are being counted
+    pub fn end_tokens(&self) -> Option<&HashMap<Token<T>, f64>> {
+        self.previous_tokens(&Token::Boundary)
+    }
+
+    /// Number of distinct last_token to next_token transitions
+    pub fn len(&self) -> usize {
+        self.transitions.values().map(HashMap::len).sum()
+    }
+
+    /// Whether no transitions have been counted
+    pub fn is_empty(&self) -> bool {
+        self.transitions.is_empty()
+    }
+
+    /// Keep only the transitions keep returns true for, given the last_token, next_token and
+    /// count, dropping last_tokens left with no transitions and freeing the memory they used
+    ///
+    /// Backward counts and provenance are left as they are
+    pub fn retain<F: FnMut(&Token<T>, &Token<T>, f64) -> bool>(&mut self, keep: F) {
+        retain(&mut self.transitions, keep);
+    }
+
+    /// Keep only the backward transitions keep returns true for, given the next_token,
+    /// last_token and count, if backward transitions are being counted
+    pub fn retain_backward<F: FnMut(&Token<T>, &Token<T>, f64) -> bool>(&mut self, keep: F) {
+        if let Some(backward) = &mut self.backward {
+            retain(backward, keep);
+        }
+    }
+
+    /// Rename tokens, both as last_tokens and next_tokens, adding up the counts of transitions
+    /// that end up the same
+    ///
+    /// Backward counts and provenance are dropped, since they no longer line up
+    pub fn map_tokens<F: FnMut(&Token<T>) -> Token<T>>(&mut self, mut rename: F) {
+        let mut transitions: TransitionMap<T> = HashMap::new();
+        for (last_token, next_tokens) in self.transitions.drain() {
+            let renamed = transitions.entry(rename(&last_token)).or_default();
+            for (next_token, count) in next_tokens {
+                *renamed.entry(rename(&next_token)).or_default() += count;
+            }
+        }
+
+        self.transitions = transitions;
+        self.backward = None;
+        self.provenance = None;
+    }
+}
+
+impl Transitions {
+    /// Estimated heap memory used by the forward and backward counts, in bytes, leaving out
+    /// provenance
+    ///
+    /// This is the memory-profiling estimate when that feature is enabled. Otherwise it is a
+    /// rougher estimate from the number of entries and the length of their tokens.
+    pub fn memory_usage(&self) -> usize {
+        #[cfg(feature = "memory-profiling")]
+        {
+            self.transitions.dynamic_usage() + self.backward.dynamic_usage()
+        }
+        #[cfg(not(feature = "memory-profiling"))]
+        {
+            map_usage(&self.transitions) + self.backward.as_ref().map_or(0, map_usage)
+        }
+    }
+}
+
+/// Rough heap memory used by a transitions map, ignoring spare capacity
+#[cfg(not(feature = "memory-profiling"))]
+fn map_usage(map: &TransitionMap<String>) -> usize {
+    fn token_usage(token: &Token) -> usize {
+        match token {
+            Token::Token(value) => value.capacity(),
+            Token::Boundary => 0,
+        }
+    }
+
+    map.iter()
+        .map(|(last_token, next_tokens)| {
+            let next_usage: usize = next_tokens.keys()
+                .map(|next_token| std::mem::size_of::<(Token, f64)>() + token_usage(next_token))
+                .sum();
+            std::mem::size_of::<(Token, HashMap<Token, f64>)>() + token_usage(last_token) + next_usage
+        })
+        .sum()
+}
+
+/// Transitions serialize as the forward counts only, in the shape sketched on
+/// [`crate::train_with_tokens`], with sorted keys so exported models diff cleanly
+#[cfg(feature = "serde")]
+impl serde::Serialize for Transitions {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let sorted: BTreeMap<&Token, BTreeMap<&Token, SerializedCount>> = self.transitions.iter()
+            .map(|(last_token, next_tokens)| {
+                let counts = next_tokens.iter().map(|(token, count)| (token, SerializedCount(*count)));
+                (last_token, counts.collect())
+            })
+            .collect();
+        sorted.serialize(serializer)
+    }
+}
+
+/// A count that serializes as an integer when it is a whole number, as it is unless decayed
+#[cfg(feature = "serde")]
+struct SerializedCount(f64);
+
+#[cfg(feature = "serde")]
+impl serde::Serialize for SerializedCount {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self.0.fract() == 0.0 && self.0 <= u64::MAX as f64 {
+            true => serializer.serialize_u64(self.0 as u64),
+            false => serializer.serialize_f64(self.0),
+        }
+    }
+}
+
+/// Reads forward counts, rejecting states with no tokens to pick and counts below MIN_COUNT,
+/// which couldn't have come from training
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for Transitions {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        use serde::de::Error;
+
+        let transitions = TransitionMap::<String>::deserialize(deserializer)?;
+        for (last_token, next_tokens) in &transitions {
+            if next_tokens.is_empty() {
+                return Err(D::Error::custom(format!("{:?} has no next tokens", last_token)));
+            }
+            let invalid = next_tokens.iter().find(|(_, count)| !(**count >= MIN_COUNT && count.is_finite()));
+            if let Some((next_token, count)) = invalid {
+                return Err(D::Error::custom(format!(
+                    "{:?} to {:?} has a count of {}, below {}", last_token, next_token, count, MIN_COUNT
+                )));
+            }
+        }
+
+        Ok(Transitions { transitions, backward: None, provenance: None })
+    }
+}
+
+#[cfg(feature = "serde")]
+impl Transitions {
+    /// Export the forward counts as pretty printed JSON
+    pub fn to_json(&self) -> String {
+        // A map of strings to maps of strings to numbers can always be serialized
+        serde_json::to_string_pretty(self).unwrap()
+    }
+
+    /// Import forward counts exported by to_json, or written by hand in the same shape
+    pub fn from_json(json: &str) -> Result<Transitions, serde_json::Error> {
+        serde_json::from_str(json)
+    }
+
+    /// Export the forward counts as YAML
+    pub fn to_yaml(&self) -> String {
+        serde_norway::to_string(self).unwrap()
+    }
+
+    /// Import forward counts exported by to_yaml, or written by hand in the same shape
+    pub fn from_yaml(yaml: &str) -> Result<Transitions, serde_norway::Error> {
+        serde_norway::from_str(yaml)
+    }
+}
+
+/// Keep only the from_token to to_token counts keep returns true for, then shrink the map to fit
+fn retain<T: Hash + Eq, F: FnMut(&Token<T>, &Token<T>, f64) -> bool>(
+    map: &mut TransitionMap<T>, mut keep: F
+) {
+    for (from_token, to_tokens) in map.iter_mut() {
+        to_tokens.retain(|to_token, count| keep(from_token, to_token, *count));
+        to_tokens.shrink_to_fit();
+    }
+    map.retain(|_, to_tokens| !to_tokens.is_empty());
+    map.shrink_to_fit();
+}
+
+/// Add amount to the from_token to to_token count in a transitions map, dropping the count if it
+/// falls below MIN_COUNT
+fn count<T: Hash + Eq + Clone>(
+    map: &mut TransitionMap<T>, from_token: &Token<T>, to_token: &Token<T>, amount: f64
+) {
+    // Get collected transitions from from_token
+    let token_trans = map
+        .entry(from_token.clone())
+        .or_default();
+
+    // Add amount to the transition to to_token
+    let count = token_trans.entry(to_token.clone()).or_insert(0.0);
+    *count += amount;
+
+    if *count < MIN_COUNT {
+        token_trans.remove(to_token);
+        if token_trans.is_empty() {
+            map.remove(from_token);
+        }
+    }
+}
+
+/// Multiply every count in a transitions map by factor, dropping counts that fall below MIN_COUNT
+fn decay<T: Hash + Eq>(map: &mut TransitionMap<T>, factor: f64) {
+    for to_tokens in map.values_mut() {
+        for count in to_tokens.values_mut() {
+            *count *= factor;
+        }
+    }
+    retain(map, |_, _, count| count >= MIN_COUNT);
+}
+
+/// Add every count in one transitions map to another, scaled by weight
+fn merge<T: Hash + Eq + Clone>(map: &mut TransitionMap<T>, other: &TransitionMap<T>, weight: f64) {
+    for (from_token, to_tokens) in other {
+        for (to_token, amount) in to_tokens {
+            count(map, from_token, to_token, amount * weight);
+        }
+    }
 }
 
 #[cfg(test)]
@@ -99,13 +465,174 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_transitions_over_other_symbols() {
+        let mut transitions: Transitions<u8> = Transitions::new();
+        transitions.count_transition(&Token::Boundary, &Token::Token(60));
+        transitions.count_transition(&Token::Token(60), &Token::Token(64));
+        transitions.count_transition(&Token::Token(60), &Token::Token(64));
+
+        assert_eq!(
+            transitions,
+            HashMap::from([
+                (Token::Boundary, HashMap::from([(Token::Token(60), 1u32)])),
+                (Token::Token(60), HashMap::from([(Token::Token(64), 2u32)])),
+            ]),
+        );
+    }
+
+    #[test]
+    fn test_backward_transitions_counts() {
+        let mut transitions = Transitions::with_backward();
+        let last_token = Token::from("last");
+        let next_token = Token::from("next");
+
+        transitions.count_transition(&last_token, &next_token);
+        transitions.count_backward_transition(&next_token, &last_token);
+
+        assert_eq!(
+            transitions.previous_tokens(&next_token),
+            Some(&HashMap::from([(last_token.clone(), 1.0)])),
+        );
+        assert_eq!(transitions.previous_tokens(&last_token), None);
+
+        // Without backward tracking, backward counts are ignored
+        let mut transitions = Transitions::new();
+        transitions.count_backward_transition(&next_token, &last_token);
+        assert_eq!(transitions.previous_tokens(&next_token), None);
+    }
+
+    #[test]
+    fn test_weighted_transitions_counts() {
+        let mut transitions = Transitions::with_backward();
+        let last_token = Token::from("last");
+        let next_token = Token::from("next");
+
+        transitions.count_weighted_transition(&last_token, &next_token, 5.0);
+        transitions.count_weighted_backward_transition(&next_token, &last_token, 5.0);
+        transitions.count_weighted_transition(&last_token, &next_token, 0.5);
+        assert_eq!(
+            transitions.next_tokens(&last_token),
+            Some(&HashMap::from([(next_token.clone(), 5.5)])),
+        );
+        assert_eq!(
+            transitions.previous_tokens(&next_token),
+            Some(&HashMap::from([(last_token.clone(), 5.0)])),
+        );
+
+        transitions.uncount_transition(&last_token, &next_token, 0.5);
+        assert_eq!(transitions.next_tokens(&last_token), Some(&HashMap::from([(next_token, 5.0)])));
+    }
+
+    #[test]
+    fn test_merge_transitions() {
+        let (a, b, c) = (Token::from("a"), Token::from("b"), Token::from("c"));
+        let mut transitions = Transitions::new();
+        transitions.count_transition(&a, &b);
+        let mut other = Transitions::new();
+        other.count_transition(&a, &b);
+        other.count_transition(&b, &c);
+
+        transitions.merge(&other, 2.0);
+        assert_eq!(transitions.next_tokens(&a), Some(&HashMap::from([(b.clone(), 3.0)])));
+        assert_eq!(transitions.next_tokens(&b), Some(&HashMap::from([(c, 2.0)])));
+        assert_eq!(transitions.previous_tokens(&b), None);
+    }
+
     #[test]
     fn test_new_transitions_is_empty() {
-        let transitions = Transitions::new();
+        let transitions: Transitions = Transitions::new();
 
         assert_eq!(
             transitions,
             HashMap::new(),
         );
     }
-}
\ No newline at end of file
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_transitions_json_and_yaml() {
+        let mut transitions = Transitions::new();
+        transitions.count_transition(&Token::Boundary, &Token::from("the"));
+        transitions.count_transition(&Token::from("the"), &Token::from("cat"));
+        transitions.count_transition(&Token::from("the"), &Token::from("cat"));
+        transitions.count_transition(&Token::from("the"), &Token::Boundary);
+
+        let json = transitions.to_json();
+        assert_eq!(json, "{\n  \"the\": {\n    \"cat\": 2,\n    \"\": 1\n  },\n  \"\": {\n    \"the\": 1\n  }\n}");
+        assert_eq!(Transitions::from_json(&json).unwrap(), transitions);
+        assert_eq!(Transitions::from_yaml(&transitions.to_yaml()).unwrap(), transitions);
+
+        assert!(Transitions::from_json(r#"{"the": {}}"#).is_err());
+        assert!(Transitions::from_json(r#"{"the": {"cat": 0}}"#).is_err());
+
+        // Decayed counts are fractional
+        transitions.decay(0.5);
+        assert!(transitions.to_json().contains("\"cat\": 1,"));
+        assert!(transitions.to_json().contains("\"the\": 0.5"));
+        assert_eq!(Transitions::from_json(&transitions.to_json()).unwrap(), transitions);
+    }
+
+    #[test]
+    fn test_decay_and_uncount() {
+        let mut transitions = Transitions::with_backward();
+        let (a, b, c) = (Token::from("a"), Token::from("b"), Token::from("c"));
+        for _ in 0..4 {
+            transitions.count_transition(&a, &b);
+            transitions.count_backward_transition(&b, &a);
+        }
+        transitions.count_transition(&a, &c);
+
+        transitions.decay(0.5);
+        assert_eq!(
+            transitions.next_tokens(&a),
+            Some(&HashMap::from([(b.clone(), 2.0), (c.clone(), 0.5)])),
+        );
+        assert_eq!(transitions.previous_tokens(&b), Some(&HashMap::from([(a.clone(), 2.0)])));
+
+        // Counts dropping below MIN_COUNT are gone
+        transitions.uncount_transition(&a, &c, 1.0);
+        transitions.uncount_transition(&a, &b, 1.0);
+        transitions.uncount_backward_transition(&b, &a, 1.0);
+        assert_eq!(transitions, HashMap::from([(a.clone(), HashMap::from([(b.clone(), 1u32)]))]));
+        assert_eq!(transitions.previous_tokens(&b), Some(&HashMap::from([(a.clone(), 1.0)])));
+
+        // Transitions that were never counted are left alone
+        transitions.uncount_transition(&b, &a, 1.0);
+        assert_eq!(transitions.next_tokens(&b), None);
+
+        transitions.decay(0.001);
+        assert!(transitions.is_empty());
+    }
+
+    #[test]
+    fn test_retain_and_map_tokens() {
+        let mut transitions = Transitions::new();
+        let (a, b, c) = (Token::from("a"), Token::from("b"), Token::from("c"));
+        transitions.count_transition(&a, &b);
+        transitions.count_transition(&a, &b);
+        transitions.count_transition(&a, &c);
+        transitions.count_transition(&c, &a);
+        assert_eq!(transitions.len(), 3);
+
+        let mut renamed = Transitions::new();
+        renamed.count_transition(&a, &b);
+        renamed.count_transition(&a, &b);
+        renamed.count_transition(&a, &c);
+        renamed.count_transition(&c, &a);
+        renamed.map_tokens(|token| match token == &c {
+            true => b.clone(),
+            false => token.clone(),
+        });
+        assert_eq!(
+            renamed,
+            HashMap::from([
+                (a.clone(), HashMap::from([(b.clone(), 3u32)])),
+                (b.clone(), HashMap::from([(a.clone(), 1u32)])),
+            ]),
+        );
+
+        transitions.retain(|_, _, count| count > 1.0);
+        assert_eq!(transitions, HashMap::from([(a, HashMap::from([(b, 2u32)]))]));
+    }
+}